@@ -0,0 +1,147 @@
+use crate::{client::RedirectChain, config_reader::Logs as LogsConfig};
+use reqwest::{blocking::Response, header::CONTENT_TYPE, Error as ReqwError, Method};
+use serde::Serialize;
+use serde_derive::Serialize;
+use std::{
+    cell::RefCell,
+    fs::File,
+    io::{self, BufWriter, Write},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+static STDOUT_PATH: &str = "-";
+
+#[derive(Serialize)]
+pub struct Record<'a> {
+    pub timestamp: u64,
+    pub url: &'a str,
+    pub parent_url: Option<&'a str>,
+    pub method: &'a str,
+    pub status: Option<u16>,
+    pub content_type: Option<&'a str>,
+    pub response_time_ms: u128,
+    pub depth: u32,
+    // URLs redirected from before the final response
+    pub redirects: &'a [String],
+}
+
+impl<'a> Record<'a> {
+    #[must_use]
+    pub fn new(
+        url: &'a str,
+        parent_url: Option<&'a str>,
+        method: &'a Method,
+        result: &'a Result<Response, ReqwError>,
+        response_time: Duration,
+        depth: u32,
+    ) -> Self {
+        let (status, content_type, redirects) = match result {
+            Ok(resp) => (
+                Some(resp.status().as_u16()),
+                resp.headers()
+                    .get(CONTENT_TYPE)
+                    .and_then(|value| value.to_str().ok()),
+                resp.extensions()
+                    .get::<RedirectChain>()
+                    .map_or(&[][..], |chain| chain.0.as_slice()),
+            ),
+            Err(err) => (err.status().map(|status| status.as_u16()), None, &[][..]),
+        };
+
+        Record {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs(),
+            url,
+            parent_url,
+            method: method.as_str(),
+            status,
+            content_type,
+            response_time_ms: response_time.as_millis(),
+            depth,
+            redirects,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct Edge<'a> {
+    pub parent_url: &'a str,
+    pub url: &'a str,
+    pub depth: u32,
+}
+
+pub struct Logs {
+    pub audit: Option<JsonLinesLog>,
+    pub graph: Option<JsonLinesLog>,
+}
+
+impl Logs {
+    pub fn new(config: &LogsConfig) -> io::Result<Self> {
+        let open = |path: &Option<String>| path.as_deref().map(JsonLinesLog::new).transpose();
+
+        Ok(Logs {
+            audit: open(&config.audit)?,
+            graph: open(&config.graph)?,
+        })
+    }
+}
+
+pub struct JsonLinesLog {
+    writer: RefCell<BufWriter<Box<dyn Write>>>,
+}
+
+impl JsonLinesLog {
+    pub fn new(path: &str) -> io::Result<Self> {
+        let writer: Box<dyn Write> = if path == STDOUT_PATH {
+            Box::new(io::stdout())
+        } else {
+            Box::new(File::options().create(true).append(true).open(path)?)
+        };
+
+        Ok(JsonLinesLog {
+            writer: RefCell::new(BufWriter::new(writer)),
+        })
+    }
+
+    // Each record is flushed, so it isn't lost if the application is killed
+    pub fn write<T: Serialize>(&self, record: &T) -> io::Result<()> {
+        let mut writer = self.writer.borrow_mut();
+
+        serde_json::to_writer(&mut *writer, record)?;
+        writer.write_all(b"\n")?;
+        writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn records_flushed() {
+        let path = std::env::temp_dir().join(format!(
+            "noisy_web_traffic_{}_graph.jsonl",
+            std::process::id()
+        ));
+        let path = path.to_str().unwrap();
+        let log = JsonLinesLog::new(path).unwrap();
+
+        log.write(&Edge {
+            parent_url: "https://example.com/",
+            url: "https://example.com/a",
+            depth: 1,
+        })
+        .unwrap();
+        // The log is still open
+        let contents = fs::read_to_string(path).unwrap();
+        fs::remove_file(path).unwrap();
+
+        assert_eq!(
+            contents,
+            "{\"parent_url\":\"https://example.com/\",\"url\":\"https://example.com/a\",\"depth\":1}\n"
+        );
+    }
+}
@@ -0,0 +1,217 @@
+use crate::{config_reader::Backoff as BackoffConfig, url_utils::get_host};
+use log::info;
+use reqwest::{blocking::Response, header::RETRY_AFTER, Error as ReqwError, StatusCode};
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    thread,
+    time::{Duration, Instant},
+};
+
+struct HostBackoff {
+    failures: u32,
+    until: Instant,
+}
+
+// Backoff of hosts throttling requests, it's kept between crawl cycles
+pub struct Backoffs<'a> {
+    config: &'a BackoffConfig,
+    hosts: RefCell<HashMap<String, HostBackoff>>,
+}
+
+impl<'a> Backoffs<'a> {
+    #[must_use]
+    pub fn new(config: &'a BackoffConfig) -> Self {
+        Backoffs {
+            config,
+            hosts: RefCell::new(HashMap::new()),
+        }
+    }
+
+    // The backoff doesn't outlast the deadline
+    pub fn wait(&self, url: &str, deadline: Option<Instant>) {
+        let host = match get_host(url) {
+            Some(host) => host,
+            None => return,
+        };
+        let time = match self.hosts.borrow().get(&host) {
+            Some(backoff) => match deadline {
+                Some(deadline) => backoff.until.min(deadline),
+                None => backoff.until,
+            }
+            .saturating_duration_since(Instant::now()),
+            None => return,
+        };
+
+        if !time.is_zero() {
+            info!(
+                "Host `{}` is backed off, sleeps for {} seconds",
+                host,
+                time.as_secs()
+            );
+            thread::sleep(time);
+        }
+    }
+
+    pub fn update(&self, url: &str, result: &Result<Response, ReqwError>) {
+        let host = match get_host(url) {
+            Some(host) => host,
+            None => return,
+        };
+        let (status, retry_after) = match result {
+            Ok(resp) => (
+                Some(resp.status()),
+                resp.headers()
+                    .get(RETRY_AFTER)
+                    .and_then(|value| value.to_str().ok())
+                    .and_then(|value| value.trim().parse().ok())
+                    .map(Duration::from_secs),
+            ),
+            Err(err) => (err.status(), None),
+        };
+
+        self.update_host(host, status, retry_after);
+    }
+
+    fn update_host(&self, host: String, status: Option<StatusCode>, retry_after: Option<Duration>) {
+        let mut hosts = self.hosts.borrow_mut();
+        match status {
+            Some(StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE) => {
+                let failures = hosts.get(&host).map_or(0, |backoff| backoff.failures) + 1;
+                let time = self.get_time(failures, retry_after);
+                info!(
+                    "Host `{}` throttles requests, backs off for {} seconds",
+                    host,
+                    time.as_secs()
+                );
+
+                let now = Instant::now();
+                hosts.insert(
+                    host,
+                    HostBackoff {
+                        failures,
+                        until: now.checked_add(time).unwrap_or(now),
+                    },
+                );
+            }
+            Some(_) => {
+                hosts.remove(&host);
+            }
+            // Connection errors don't tell whether the host still throttles requests
+            None => (),
+        }
+    }
+
+    // `Retry-After` is set by the server, so it's capped like the exponential backoff
+    fn get_time(&self, failures: u32, retry_after: Option<Duration>) -> Duration {
+        let max_time = Duration::from_secs(self.config.max_secs);
+
+        match retry_after {
+            Some(time) => time.min(max_time),
+            None => {
+                let secs = self
+                    .config
+                    .base_secs
+                    .saturating_mul(2_u64.saturating_pow(failures - 1));
+
+                Duration::from_secs(secs).min(max_time)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> BackoffConfig {
+        BackoffConfig {
+            base_secs: 2,
+            max_secs: 60,
+        }
+    }
+
+    fn remaining(backoffs: &Backoffs, host: &str) -> Duration {
+        backoffs.hosts.borrow()[host]
+            .until
+            .saturating_duration_since(Instant::now())
+    }
+
+    #[test]
+    fn exponential_time() {
+        let config = config();
+        let backoffs = Backoffs::new(&config);
+
+        assert_eq!(backoffs.get_time(1, None), Duration::from_secs(2));
+        assert_eq!(backoffs.get_time(3, None), Duration::from_secs(8));
+        assert_eq!(backoffs.get_time(10, None), Duration::from_secs(60));
+        assert_eq!(backoffs.get_time(u32::MAX, None), Duration::from_secs(60));
+    }
+
+    #[test]
+    fn retry_after_is_capped() {
+        let config = config();
+        let backoffs = Backoffs::new(&config);
+
+        assert_eq!(
+            backoffs.get_time(1, Some(Duration::from_secs(5))),
+            Duration::from_secs(5)
+        );
+        assert_eq!(
+            backoffs.get_time(1, Some(Duration::from_secs(u64::MAX))),
+            Duration::from_secs(60)
+        );
+    }
+
+    #[test]
+    fn throttled_host() {
+        let config = config();
+        let backoffs = Backoffs::new(&config);
+        let host = "example.com";
+
+        backoffs.update_host(
+            host.to_string(),
+            Some(StatusCode::TOO_MANY_REQUESTS),
+            Some(Duration::from_secs(u64::MAX)),
+        );
+        assert!(remaining(&backoffs, host) <= Duration::from_secs(60));
+
+        backoffs.update_host(
+            host.to_string(),
+            Some(StatusCode::SERVICE_UNAVAILABLE),
+            None,
+        );
+        assert_eq!(backoffs.hosts.borrow()[host].failures, 2);
+        assert!(remaining(&backoffs, host) <= Duration::from_secs(4));
+
+        backoffs.update_host(host.to_string(), None, None);
+        assert_eq!(backoffs.hosts.borrow()[host].failures, 2);
+
+        backoffs.update_host(host.to_string(), Some(StatusCode::OK), None);
+        assert!(backoffs.hosts.borrow().get(host).is_none());
+    }
+
+    #[test]
+    fn wait_capped_by_deadline() {
+        let config = config();
+        let backoffs = Backoffs::new(&config);
+
+        backoffs.update_host(
+            "example.com".to_string(),
+            Some(StatusCode::TOO_MANY_REQUESTS),
+            None,
+        );
+        backoffs.update_host(
+            "example.com".to_string(),
+            Some(StatusCode::TOO_MANY_REQUESTS),
+            Some(Duration::from_secs(60)),
+        );
+
+        let now = Instant::now();
+        backoffs.wait(
+            "https://example.com/a",
+            Some(now + Duration::from_millis(50)),
+        );
+        assert!(now.elapsed() < Duration::from_secs(5));
+    }
+}
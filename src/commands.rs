@@ -0,0 +1,239 @@
+use crate::{
+    client::{read_text, Client},
+    config_reader::Config,
+    crawl::{
+        get_content_type, get_page_hrefs, get_page_urls, get_random_sleep, get_refresh_href,
+        is_x_robots_nofollow,
+    },
+    machine_config::MachineConfig,
+    parser::{get_url, RejectReason},
+};
+use log::info;
+use rand::{
+    distributions::{Alphanumeric, DistString},
+    seq::SliceRandom,
+    thread_rng, Rng,
+};
+use reqwest::Method;
+use std::{collections::BTreeSet, time::Instant};
+
+pub fn print_effective_config(config: &Config) {
+    print!(
+        "{}",
+        serde_yaml::to_string(config).expect("Failed to serialize config")
+    );
+}
+
+pub fn sample_delays(config: &Config, count: usize) {
+    assert!(count > 0, "Number of samples must be positive");
+
+    let mut delays: Vec<u32> = (0..count).map(|_| get_random_sleep(config)).collect();
+    delays.sort_unstable();
+
+    let percentile = |percent: usize| delays[(delays.len() - 1) * percent / 100];
+    let mean = delays.iter().map(|&delay| f64::from(delay)).sum::<f64>() / count as f64;
+
+    println!("Samples: {}", count);
+    println!("Min: {} seconds", delays[0]);
+    println!("Max: {} seconds", delays[count - 1]);
+    println!("Mean: {:.2} seconds", mean);
+    for percent in [50, 90, 99] {
+        println!("P{}: {} seconds", percent, percentile(percent));
+    }
+}
+
+// Measures throughput of the checks of child URLs over generated URLs of the root URLs
+pub fn bench_matching(config: &Config, machine_config: &MachineConfig, count: usize) {
+    assert!(count > 0, "Number of URLs must be positive");
+    assert!(!config.urls.roots.is_empty(), "Root URLs are empty");
+
+    let mut rng = thread_rng();
+    let urls: Vec<String> = (0..count)
+        .map(|_| {
+            let root_url = config.urls.roots.choose(&mut rng).unwrap();
+            let segments: Vec<String> = (0..rng.gen_range(0..6))
+                .map(|_| {
+                    let len = rng.gen_range(1..12);
+                    Alphanumeric.sample_string(&mut rng, len)
+                })
+                .collect();
+
+            format!(
+                "{}/{}?id={}",
+                root_url.trim_end_matches('/'),
+                segments.join("/"),
+                rng.gen::<u32>()
+            )
+        })
+        .collect();
+
+    let now = Instant::now();
+    let accepted = urls
+        .iter()
+        .filter(|url| check_child_url(url, &config.urls.roots, config, machine_config).is_ok())
+        .count();
+    let elapsed = now.elapsed().as_secs_f64();
+
+    println!("URLs: {}", count);
+    println!("Accepted: {}", accepted);
+    println!("Time: {:.3} seconds", elapsed);
+    println!("Throughput: {:.0} URLs/sec", count as f64 / elapsed);
+}
+
+// Fetches only the root URLs and prints their child URLs instead of crawling them
+pub fn discover(client: &Client, config: &Config, machine_config: &MachineConfig) {
+    for root_url in &config.urls.roots {
+        let resp = match client.request(Method::GET, root_url, None, None) {
+            Ok(resp) => resp,
+            Err(err) => {
+                info!("Failed to discover URL `{}`: {}", root_url, err);
+                continue;
+            }
+        };
+        if config.client.respect_x_robots_tag && is_x_robots_nofollow(&resp) {
+            info!("URL `{}` doesn't allow to follow its links", root_url);
+            continue;
+        }
+        let url = resp.url().clone();
+        let content_type = get_content_type(&resp);
+        let refresh = get_refresh_href(&resp);
+        let html = match read_text(resp, config.client.max_body_size) {
+            Ok(html) => html,
+            Err(err) => {
+                info!("Couldn't get HTML from URL `{}`: {}", root_url, err);
+                continue;
+            }
+        };
+
+        let child_urls: BTreeSet<String> = get_page_urls(
+            config,
+            machine_config,
+            &url,
+            &html,
+            content_type.as_deref(),
+            refresh.as_deref(),
+            root_url,
+        )
+        .unwrap_or_default()
+        .into_iter()
+        .collect();
+        for child_url in child_urls {
+            println!("{}", child_url);
+        }
+    }
+}
+
+// Checks a URL as a child URL of any root URL without crawling
+pub fn test_url(url: &str, config: &Config, machine_config: &MachineConfig) {
+    match check_child_url(url, &config.urls.roots, config, machine_config) {
+        Ok(child_url) => println!("Accepted: `{}` -> `{}`", url, child_url),
+        Err(reason) => println!("Rejected: `{}`: {}", url, reason),
+    }
+}
+
+// Fetches a URL and prints its hrefs found like the crawler does, with their child URLs or rejections
+pub fn dump_dom(url: &str, client: &Client, config: &Config, machine_config: &MachineConfig) {
+    let resp = client
+        .request(Method::GET, url, None, None)
+        .unwrap_or_else(|err| panic!("Failed to fetch URL `{}`: {}", url, err));
+    if config.client.respect_x_robots_tag && is_x_robots_nofollow(&resp) {
+        println!("URL `{}` doesn't allow to follow its links", url);
+        return;
+    }
+    let page_url = resp.url().clone();
+    let content_type = get_content_type(&resp);
+    let refresh = get_refresh_href(&resp);
+    let html = read_text(resp, config.client.max_body_size)
+        .unwrap_or_else(|err| panic!("Couldn't get HTML from URL `{}`: {}", url, err));
+
+    let page_hrefs = match get_page_hrefs(
+        config,
+        machine_config,
+        &page_url,
+        &html,
+        content_type.as_deref(),
+        refresh.as_deref(),
+        url,
+    ) {
+        Some(page_hrefs) => page_hrefs,
+        None => {
+            println!("URL `{}` doesn't allow to follow its links", url);
+            return;
+        }
+    };
+    println!("Found {} hrefs in `{}`", page_hrefs.len(), page_url);
+
+    for (href, result) in page_hrefs {
+        match result {
+            Ok(child_url) => println!("Accepted: `{}` -> `{}`", href, child_url),
+            Err(reason) => println!("Rejected: `{}`: {}", href, reason),
+        }
+    }
+}
+
+// Child URL of the first root URL it's in scope of, other checks don't depend on the root URL
+fn check_child_url(
+    url: &str,
+    root_urls: &[String],
+    config: &Config,
+    machine_config: &MachineConfig,
+) -> Result<String, RejectReason> {
+    let mut result = Err(RejectReason::OutOfScope);
+    for root_url in root_urls {
+        result = get_url(
+            url,
+            url,
+            &machine_config.blacklist.childs,
+            &config.urls,
+            root_url,
+        );
+        if result != Err(RejectReason::OutOfScope) {
+            break;
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config_reader::Scope, machine_config::BlacklistUrls};
+
+    #[test]
+    fn child_url_reject_reasons() {
+        let mut config: Config = serde_yaml::from_str(include_str!("../config.yaml")).unwrap();
+        config.urls.scope = Scope::SameHost;
+        config.urls.max_url_length = 64;
+        let machine_config = MachineConfig {
+            blacklist: BlacklistUrls {
+                roots: vec![],
+                childs: vec!["https://example.com/private".to_string()],
+                hrefs: vec![],
+                types: vec![],
+            },
+        };
+        let root_urls = [
+            "https://example.com/".to_string(),
+            "https://example.org/".to_string(),
+        ];
+        let check = |url: &str| check_child_url(url, &root_urls, &config, &machine_config);
+
+        assert_eq!(
+            check("https://example.com/private/a"),
+            Err(RejectReason::Blacklisted {
+                prefix: "https://example.com/private".to_string()
+            })
+        );
+        assert_eq!(check("https://other.com/a"), Err(RejectReason::OutOfScope));
+        assert_eq!(
+            check(&format!("https://example.org/{}", "a".repeat(64))),
+            Err(RejectReason::Filtered("length 84 more than 64".to_string()))
+        );
+        // Normalized like child URLs of the crawler
+        assert_eq!(
+            check("https://example.org/a?utm_source=b#c").as_deref(),
+            Ok("https://example.org/a")
+        );
+    }
+}
@@ -1,45 +1,356 @@
+use rand::distributions::WeightedIndex;
 use serde_derive::{Deserialize, Serialize};
-use std::{fs::File, io::Read, path::Path};
+use std::{
+    collections::HashMap,
+    fmt::{self, Display, Formatter},
+    fs::File,
+    io::Read,
+    net::IpAddr,
+    path::Path,
+};
+use url::Url;
 
 #[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Client {
     pub max_depth: u32,
+    #[serde(default)]
+    pub dry_run: bool,
     pub min_sleep: u32,
     pub max_sleep: u32,
     pub max_timeout: u32,
+    #[serde(default = "default_connect_timeout")]
+    pub connect_timeout: u32,
     pub max_redirections: u32,
     pub max_failures: u32,
+    #[serde(default)]
+    pub breadth_per_depth: Option<u32>,
+    #[serde(default)]
+    pub prefer_new_hosts: bool,
+    #[serde(default = "default_follow_probability")]
+    pub follow_probability: f64,
+    #[serde(default)]
+    pub max_subtree_duration_secs: Option<u64>,
+    #[serde(default = "default_max_body_size")]
+    pub max_body_size: u64,
+    #[serde(default)]
+    pub max_dom_size: Option<u64>,
+    #[serde(default)]
+    pub max_total_bytes: Option<u64>,
+    #[serde(default)]
+    pub hard_deadline_secs: Option<u64>,
+    #[serde(default)]
+    pub backoff: Backoff,
+    #[serde(default)]
+    pub trap_detection: Option<TrapDetection>,
+    #[serde(default)]
+    pub proxy: Option<String>,
+    #[serde(default)]
+    pub proxy_fallback: ProxyFallback,
+    #[serde(default)]
+    pub local_addresses: Vec<IpAddr>,
+    #[serde(default)]
+    pub http_version: HttpVersion,
+    #[serde(default)]
+    pub referrer_policy: ReferrerPolicy,
+    #[serde(default)]
+    pub headers: Headers,
+    #[serde(default)]
+    pub from_email: Option<String>,
+    #[serde(default)]
+    pub force_identity_encoding: bool,
+    #[serde(default)]
+    pub accept_languages: Vec<String>,
+    #[serde(default)]
+    pub depth_jitter: Option<DepthJitter>,
+    #[serde(default)]
+    pub interval_secs: Option<u64>,
+    #[serde(default)]
+    pub use_canonical: bool,
+    #[serde(default)]
+    pub streaming_parser: bool,
+    #[serde(default)]
+    pub respect_meta_robots: bool,
+    #[serde(default)]
+    pub respect_x_robots_tag: bool,
+    #[serde(default)]
+    pub header_hints: HashMap<String, HeaderHint>,
+    #[serde(default)]
+    pub scan_comments: bool,
+    #[serde(default)]
+    pub content_selector: Option<String>,
+    #[serde(default)]
+    pub feed_types: Vec<String>,
+    #[serde(default)]
+    pub scan_json: bool,
+    #[serde(default)]
+    pub methods: Methods,
+    #[serde(default)]
+    pub schemes: HashMap<String, Scheme>,
+}
+
+// Defaults of fields missing in older configs, so they keep working as before
+fn default_connect_timeout() -> u32 {
+    5
+}
+
+fn default_follow_probability() -> f64 {
+    1.0
+}
+
+fn default_max_body_size() -> u64 {
+    u64::MAX
+}
+
+fn default_max_url_length() -> usize {
+    usize::MAX
+}
+
+#[derive(Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HttpVersion {
+    #[default]
+    Auto,
+    Http1,
+    Http2,
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HeaderHint {
+    Stop,
+    Blacklist,
+}
+
+#[derive(Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProxyFallback {
+    #[default]
+    Fail,
+    Direct,
+}
+
+#[derive(Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ReferrerPolicy {
+    StrictOriginWhenCrossOrigin,
+    #[default]
+    NoReferrer,
+    UnsafeUrl,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Headers {
+    pub extra: HashMap<String, String>,
+    pub redact: Vec<String>,
+    pub randomize_order: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Backoff {
+    pub base_secs: u64,
+    pub max_secs: u64,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Backoff {
+            base_secs: 10,
+            max_secs: 600,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TrapDetection {
+    pub max_similar_pages: u32,
+    pub size_ratio: f64,
 }
 
 #[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Scheme {
+    pub max_timeout: u32,
+    #[serde(default)]
+    pub proxy: Option<String>,
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct DepthJitter {
+    pub min_depth: u32,
+    pub max_depth: u32,
+}
+
+impl TryFrom<String> for DepthJitter {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let error = || format!("Invalid depth jitter `{}`, expected `min-max`", value);
+
+        let (min_depth, max_depth) = value.split_once('-').ok_or_else(error)?;
+        let min_depth = min_depth.trim().parse().map_err(|_| error())?;
+        let max_depth = max_depth.trim().parse().map_err(|_| error())?;
+        if min_depth > max_depth {
+            return Err(error());
+        }
+
+        Ok(DepthJitter {
+            min_depth,
+            max_depth,
+        })
+    }
+}
+
+impl From<DepthJitter> for String {
+    fn from(value: DepthJitter) -> Self {
+        format!("{}-{}", value.min_depth, value.max_depth)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Methods {
+    pub get: u32,
+    pub head: u32,
+    pub options: u32,
+}
+
+// Only `GET` requests, like before the weights
+impl Default for Methods {
+    fn default() -> Self {
+        Methods {
+            get: 1,
+            head: 0,
+            options: 0,
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct BlacklistUrls {
     pub childs: Vec<String>,
     pub hrefs: Vec<String>,
     pub types: Vec<String>,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Urls {
     pub roots: Vec<String>,
+    #[serde(default)]
+    pub roots_file: Option<String>,
     pub blacklist: BlacklistUrls,
+    #[serde(default)]
+    pub policy: Policy,
+    #[serde(default)]
+    pub whitelist: Vec<String>,
+    #[serde(default = "default_max_url_length")]
+    pub max_url_length: usize,
+    #[serde(default)]
+    pub max_subdomain_depth: Option<usize>,
+    #[serde(default)]
+    pub max_path_segments: Option<usize>,
+    #[serde(default)]
+    pub max_query_params: Option<usize>,
+    #[serde(default)]
+    pub reject_userinfo: bool,
+    #[serde(default)]
+    pub require_standard_ports: bool,
+    #[serde(default)]
+    pub max_distinct_ports: Option<usize>,
+    #[serde(default)]
+    pub tlds: Vec<String>,
+    #[serde(default)]
+    pub scope: Scope,
+    #[serde(default)]
+    pub check_redirects: bool,
+    #[serde(default)]
+    pub normalize: Normalize,
+    #[serde(default)]
+    pub path_weights: Vec<PathWeight>,
+    #[serde(default)]
+    pub scheme_weights: HashMap<String, u32>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PathWeight {
+    pub glob: String,
+    pub weight: u32,
+}
+
+#[derive(Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Policy {
+    #[default]
+    Allow,
+    Deny,
+}
+
+#[derive(Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Scope {
+    #[default]
+    Any,
+    SameHost,
+    SameSite,
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Normalize {
+    pub strip_fragment: bool,
+    pub collapse_www: bool,
+    pub unicode_paths: bool,
+    pub tracking_params: Vec<String>,
 }
 
 #[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct UserAgent {
     pub generate: bool,
     pub current: Option<String>,
+    #[serde(default)]
+    pub values: Vec<String>,
+    #[serde(default)]
+    pub values_file: Option<String>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Soft404 {
+    pub detect: bool,
+    pub patterns: Vec<String>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Logs {
+    pub audit: Option<String>,
+    pub graph: Option<String>,
 }
 
 #[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct MachineConfig {
     pub name: String,
 }
 
 #[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Config {
     pub client: Client,
     pub urls: Urls,
     pub user_agent: UserAgent,
+    #[serde(default)]
+    pub soft_404: Soft404,
+    #[serde(default)]
+    pub logs: Logs,
     pub machine_config: MachineConfig,
 }
 
@@ -51,5 +362,449 @@ pub fn parse_config(path: &str) -> serde_yaml::Result<Config> {
         .read_to_string(&mut contents)
         .expect("Failed to read config");
 
-    serde_yaml::from_str::<Config>(&contents)
+    let mut config = serde_yaml::from_str::<Config>(&contents)?;
+    if let Some(roots_file) = &config.urls.roots_file {
+        let roots = read_urls_file(roots_file);
+
+        config.urls.roots.extend(roots);
+    }
+    if let Some(from_email) = &config.client.from_email {
+        assert!(
+            is_email(from_email),
+            "Invalid `From` email `{}`",
+            from_email
+        );
+    }
+    // `gen_bool` panics on probabilities out of the range, NaN included
+    assert!(
+        (0.0..=1.0).contains(&config.client.follow_probability),
+        "Invalid follow probability `{}`, expected a value from 0.0 to 1.0",
+        config.client.follow_probability
+    );
+    // The same weights are sampled for protocol-relative child URLs, so they can't fail mid-crawl
+    if let Some(scheme) = config
+        .urls
+        .scheme_weights
+        .keys()
+        .find(|scheme| !["http", "https"].contains(&scheme.as_str()))
+    {
+        panic!(
+            "Invalid scheme `{}` in scheme weights: only `http` and `https` are supported",
+            scheme
+        );
+    }
+    if !config.urls.scheme_weights.is_empty() {
+        if let Err(err) = WeightedIndex::new(config.urls.scheme_weights.values()) {
+            panic!("Invalid scheme weights: {}", err);
+        }
+    }
+    if let Some(values_file) = &config.user_agent.values_file {
+        let values = read_lines_file(values_file)
+            .into_iter()
+            .map(|(_, value)| value);
+
+        config.user_agent.values.extend(values);
+    }
+
+    Ok(config)
+}
+
+// Plausible `local@example.com`, not a full RFC 5322 check
+fn is_email(value: &str) -> bool {
+    match value.split_once('@') {
+        Some((local, domain)) => {
+            !local.is_empty()
+                && domain.contains('.')
+                && !domain.starts_with('.')
+                && !domain.ends_with('.')
+                && !domain.contains('@')
+                && !value
+                    .chars()
+                    .any(|char| char.is_whitespace() || char.is_control())
+        }
+        None => false,
+    }
+}
+
+fn read_urls_file(path: &str) -> Vec<String> {
+    read_lines_file(path)
+        .into_iter()
+        .map(|(number, line)| {
+            Url::parse(&line).unwrap_or_else(|err| {
+                panic!(
+                    "Invalid URL `{}` on line {} of `{}`: {}",
+                    line, number, path, err
+                )
+            });
+            line
+        })
+        .collect()
+}
+
+// Newline-delimited values with their line numbers, blank lines and lines starting with `#` are skipped
+fn read_lines_file(path: &str) -> Vec<(usize, String)> {
+    let mut contents = String::new();
+
+    File::open(Path::new(path))
+        .unwrap_or_else(|err| panic!("Failed to open file `{}`: {}", path, err))
+        .read_to_string(&mut contents)
+        .unwrap_or_else(|err| panic!("Failed to read file `{}`: {}", path, err));
+
+    contents
+        .lines()
+        .enumerate()
+        .map(|(index, line)| (index + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(number, line)| (number, line.to_string()))
+        .collect()
+}
+
+pub enum Lint {
+    EmptyRoots,
+    ZeroMaxDepth,
+    InvalidSleepRange { min_sleep: u32, max_sleep: u32 },
+    RootShadowedByChilds { root: String, child: String },
+    AllHrefsBlacklisted { href: String },
+    EmptyWhitelist,
+}
+
+impl Display for Lint {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Lint::EmptyRoots => write!(f, "Root URLs are empty, nothing will be crawled"),
+            Lint::ZeroMaxDepth => write!(f, "Maximum depth is 0, nothing will be crawled"),
+            Lint::InvalidSleepRange {
+                min_sleep,
+                max_sleep,
+            } => write!(
+                f,
+                "Minimum sleep time ({}) must be less than maximum sleep time ({})",
+                min_sleep, max_sleep
+            ),
+            Lint::RootShadowedByChilds { root, child } => write!(
+                f,
+                "Blacklisted child URL `{}` covers root URL `{}`, its child URLs will never be crawled",
+                child, root
+            ),
+            Lint::EmptyWhitelist => write!(
+                f,
+                "Whitelist is empty with the deny policy, child URLs will never be crawled"
+            ),
+            Lint::AllHrefsBlacklisted { href } => write!(
+                f,
+                "Blacklisted href `{}` covers all relative hrefs, they will never be crawled",
+                href
+            ),
+        }
+    }
+}
+
+impl Config {
+    #[must_use]
+    pub fn lint(&self) -> Vec<Lint> {
+        let mut lints = vec![];
+
+        if self.urls.roots.is_empty() {
+            lints.push(Lint::EmptyRoots);
+        }
+        if self.client.max_depth == 0 {
+            lints.push(Lint::ZeroMaxDepth);
+        }
+        if self.client.min_sleep >= self.client.max_sleep {
+            lints.push(Lint::InvalidSleepRange {
+                min_sleep: self.client.min_sleep,
+                max_sleep: self.client.max_sleep,
+            });
+        }
+        for root in &self.urls.roots {
+            for child in &self.urls.blacklist.childs {
+                if root.starts_with(child) {
+                    lints.push(Lint::RootShadowedByChilds {
+                        root: root.clone(),
+                        child: child.clone(),
+                    });
+                }
+            }
+        }
+        if let (Policy::Deny, true) = (self.urls.policy, self.urls.whitelist.is_empty()) {
+            lints.push(Lint::EmptyWhitelist);
+        }
+        for href in &self.urls.blacklist.hrefs {
+            if href.is_empty() || href == "/" {
+                lints.push(Lint::AllHrefsBlacklisted { href: href.clone() });
+            }
+        }
+
+        lints
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Config without the fields and sections added after the first release
+    const OLD_CONFIG: &str = r#"
+client:
+  max_depth: 30
+  min_sleep: 1
+  max_sleep: 5
+  max_timeout: 15
+  max_redirections: 7
+  max_failures: 5
+urls:
+  roots:
+    - https://example.com
+  blacklist:
+    childs: []
+    hrefs: []
+    types: []
+user_agent:
+  generate: false
+  current: ~
+machine_config:
+  name: machine_config
+"#;
+
+    #[test]
+    fn old_config_defaults() {
+        let config: Config = serde_yaml::from_str(OLD_CONFIG).unwrap();
+        let printed = serde_yaml::to_string(&config).unwrap();
+
+        for line in [
+            "dry_run: false",
+            "follow_probability: 1.0",
+            "referrer_policy: no-referrer",
+            "proxy_fallback: fail",
+            "policy: allow",
+            "scope: any",
+            "check_redirects: false",
+            "soft_404:\n  detect: false",
+            "logs:\n  audit: null\n  graph: null",
+            "methods:\n    get: 1\n    head: 0\n    options: 0",
+        ] {
+            assert!(printed.contains(line), "`{}` isn't in:\n{}", line, printed);
+        }
+        assert!(config.lint().is_empty());
+    }
+
+    #[test]
+    fn full_config() {
+        let config: Config = serde_yaml::from_str(include_str!("../config.yaml")).unwrap();
+
+        assert!(config.lint().is_empty());
+    }
+
+    #[test]
+    fn lint_root_shadowed_by_childs() {
+        let mut config: Config = serde_yaml::from_str(OLD_CONFIG).unwrap();
+        config.urls.blacklist.childs = vec!["https://example".to_string()];
+
+        assert!(matches!(
+            config.lint().as_slice(),
+            [Lint::RootShadowedByChilds { root, child }]
+                if root == "https://example.com" && child == "https://example"
+        ));
+    }
+
+    #[test]
+    fn lint_contradictions() {
+        let mut config: Config = serde_yaml::from_str(OLD_CONFIG).unwrap();
+        config.urls.roots.clear();
+        config.client.max_depth = 0;
+        config.client.min_sleep = 5;
+        config.urls.policy = Policy::Deny;
+        config.urls.blacklist.hrefs = vec!["/".to_string()];
+
+        let lints: Vec<String> = config.lint().iter().map(ToString::to_string).collect();
+        assert_eq!(
+            lints,
+            [
+                Lint::EmptyRoots.to_string(),
+                Lint::ZeroMaxDepth.to_string(),
+                Lint::InvalidSleepRange {
+                    min_sleep: 5,
+                    max_sleep: 5
+                }
+                .to_string(),
+                Lint::EmptyWhitelist.to_string(),
+                Lint::AllHrefsBlacklisted {
+                    href: "/".to_string()
+                }
+                .to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn depth_jitter() {
+        let depth_jitter = |value: &str| {
+            DepthJitter::try_from(value.to_string())
+                .map(|depth_jitter| (depth_jitter.min_depth, depth_jitter.max_depth))
+        };
+
+        assert_eq!(depth_jitter("3-7"), Ok((3, 7)));
+        assert_eq!(depth_jitter(" 3 - 7 "), Ok((3, 7)));
+        assert_eq!(depth_jitter("5-5"), Ok((5, 5)));
+        for value in ["7-3", "3", "3-", "-7", "a-b", "-1-3", ""] {
+            assert!(depth_jitter(value).is_err(), "{}", value);
+        }
+        assert_eq!(
+            String::from(DepthJitter {
+                min_depth: 3,
+                max_depth: 7
+            }),
+            "3-7"
+        );
+    }
+
+    // File with the contents in the temporary directory, removed on drop even if the test panics
+    struct TempFile {
+        path: String,
+    }
+
+    impl TempFile {
+        fn new(name: &str, contents: &str) -> Self {
+            let path = std::env::temp_dir().join(format!(
+                "noisy_web_traffic_{}_{}",
+                std::process::id(),
+                name
+            ));
+            std::fs::write(&path, contents).unwrap();
+
+            Self {
+                path: path.to_str().unwrap().to_string(),
+            }
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+
+    #[test]
+    fn roots_file_merged() {
+        let roots_file = TempFile::new(
+            "roots.txt",
+            "# Roots\nhttps://a.example.com\n\n  https://b.example.com  \n#https://c.example.com\n",
+        );
+        let config_file = TempFile::new(
+            "roots_config.yaml",
+            &OLD_CONFIG.replace(
+                "  blacklist:",
+                &format!("  roots_file: {}\n  blacklist:", roots_file.path),
+            ),
+        );
+
+        let config = parse_config(&config_file.path).unwrap();
+
+        assert_eq!(
+            config.urls.roots,
+            [
+                "https://example.com",
+                "https://a.example.com",
+                "https://b.example.com"
+            ]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "on line 3 of")]
+    fn roots_file_malformed_line() {
+        let roots_file = TempFile::new(
+            "malformed_roots.txt",
+            "https://a.example.com\n# Comment\nnot a url\n",
+        );
+
+        read_urls_file(&roots_file.path);
+    }
+
+    #[test]
+    fn email() {
+        for value in ["ops@example.com", "a.b+c@mail.example.co.uk"] {
+            assert!(is_email(value), "{}", value);
+        }
+        for value in [
+            "",
+            "ops",
+            "@example.com",
+            "ops@example",
+            "ops@.example.com",
+            "ops@example.com.",
+            "ops@a@example.com",
+            "ops @example.com",
+            "ops@example.com\r\nX-Injected: 1",
+        ] {
+            assert!(!is_email(value), "{}", value);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid scheme weights")]
+    fn zero_scheme_weights() {
+        let config_file = TempFile::new(
+            "zero_scheme_weights_config.yaml",
+            &OLD_CONFIG.replace(
+                "  blacklist:",
+                "  scheme_weights:\n    https: 0\n    http: 0\n  blacklist:",
+            ),
+        );
+
+        let _config = parse_config(&config_file.path);
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid scheme `ftp` in scheme weights")]
+    fn unsupported_scheme_weights() {
+        let config_file = TempFile::new(
+            "unsupported_scheme_weights_config.yaml",
+            &OLD_CONFIG.replace(
+                "  blacklist:",
+                "  scheme_weights:\n    https: 1\n    ftp: 1\n  blacklist:",
+            ),
+        );
+
+        let _config = parse_config(&config_file.path);
+    }
+
+    #[test]
+    fn follow_probability_bounds() {
+        for follow_probability in [0.0, 1.0] {
+            let config_file = TempFile::new(
+                "follow_probability_config.yaml",
+                &OLD_CONFIG.replace(
+                    "max_depth: 30",
+                    &format!(
+                        "max_depth: 30\n  follow_probability: {:.1}",
+                        follow_probability
+                    ),
+                ),
+            );
+
+            let config = parse_config(&config_file.path).unwrap();
+
+            assert_eq!(config.client.follow_probability, follow_probability);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid follow probability `NaN`")]
+    fn nan_follow_probability() {
+        let config_file = TempFile::new(
+            "nan_follow_probability_config.yaml",
+            &OLD_CONFIG.replace("max_depth: 30", "max_depth: 30\n  follow_probability: .nan"),
+        );
+
+        let _config = parse_config(&config_file.path);
+    }
+
+    #[test]
+    fn unknown_field() {
+        let config = OLD_CONFIG.replace("max_depth: 30", "max_depth: 30\n  max_dpeth: 3");
+
+        let err = serde_yaml::from_str::<Config>(&config).err().unwrap();
+        assert!(err.to_string().contains("max_dpeth"), "{}", err);
+    }
 }
@@ -1,66 +1,477 @@
 use crate::{
-    client::Client,
-    config_reader::Config,
+    audit::{Edge, Logs, Record},
+    backoff::Backoffs,
+    client::{read_text, Client},
+    config_reader::{Config, DepthJitter, HeaderHint},
     machine_config::{
         parse_config, write_blacklist_url_if_need, write_blacklist_urls, MachineConfig,
     },
-    parser::{get_hrefs, get_url, parse_dom, value_in_blacklist},
+    parser::{
+        check_href_acceptable, get_canonical_url, get_feed_hrefs, get_hrefs, get_json_hrefs,
+        get_url, get_url_from_href, is_nofollow, is_nofollow_directive, is_soft_404, parse_dom,
+        parse_refresh, scan_comments_hrefs, scan_hrefs, value_in_blacklist, RejectReason,
+    },
+    pause::wait_if_paused,
+    url_utils::{get_host, glob_match, UrlNormalizer},
 };
-use log::{debug, info};
+use log::{debug, info, warn};
 use rand::{distributions::Uniform, seq::SliceRandom, thread_rng, Rng};
+use reqwest::{blocking::Response, header::CONTENT_TYPE, Method, Url};
+use serde_json::Value as JsonValue;
 use std::{
+    cell::{Cell, RefCell},
+    cmp::Reverse,
+    collections::{HashMap, HashSet},
     thread::sleep as thread_sleep,
     time::{Duration, Instant},
 };
 
+static REFRESH: &str = "refresh";
+static X_ROBOTS_TAG: &str = "x-robots-tag";
+
 enum CrawlResult {
     Success,
     Failure,
 }
 
-pub fn run(client: &Client, config: &Config, roots: &[String], machine_config_path: &str) {
+#[derive(Default)]
+struct RootStats {
+    requests: Cell<u32>,
+    failed_requests: Cell<u32>,
+    discovered_urls: Cell<u32>,
+    bytes: Cell<u64>,
+}
+
+struct Context<'a> {
+    client: &'a Client,
+    config: &'a Config,
+    machine_config: &'a MachineConfig,
+    machine_config_path: &'a str,
+    logs: &'a Logs,
+    backoffs: &'a Backoffs<'a>,
+    max_depth: u32,
+    root_url: &'a str,
+    breadth: RefCell<HashMap<u32, u32>>,
+    started: Instant,
+    hosts_ports: &'a RefCell<HashMap<String, HashSet<u16>>>,
+    hosts_requests: &'a RefCell<HashMap<String, u32>>,
+    stats: RootStats,
+    budget: &'a Budget,
+    traps: RefCell<HashMap<String, PrefixSizes>>,
+}
+
+// Sizes of consecutive pages under a path prefix
+#[derive(Default)]
+struct PrefixSizes {
+    last_size: usize,
+    similar_pages: u32,
+    trapped: bool,
+}
+
+// Outcome of a crawl cycle
+#[derive(Default)]
+pub struct CrawlReport {
+    pub requests: u32,
+    // Requests without a response or with an error status
+    pub failed_requests: u32,
+    pub discovered_urls: u32,
+    pub bytes: u64,
+    pub hosts_requests: HashMap<String, u32>,
+    pub duration: Duration,
+    // The cycle was aborted by the hard deadline, not finished
+    pub deadline_reached: bool,
+}
+
+// Limits of the whole application run, shared by crawl cycles
+pub struct Budget {
+    max_total_bytes: Option<u64>,
+    total_bytes: Cell<u64>,
+    deadline: Option<Instant>,
+}
+
+impl Budget {
+    #[must_use]
+    pub fn new(config: &Config) -> Self {
+        Budget {
+            max_total_bytes: config.client.max_total_bytes,
+            total_bytes: Cell::new(0),
+            deadline: config
+                .client
+                .hard_deadline_secs
+                .map(|secs| Instant::now() + Duration::from_secs(secs)),
+        }
+    }
+
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    pub fn total_bytes(&self) -> u64 {
+        self.total_bytes.get()
+    }
+
+    fn add_bytes(&self, bytes: u64) {
+        self.total_bytes.set(self.total_bytes.get() + bytes);
+    }
+
+    pub fn bytes_exceeded(&self) -> bool {
+        self.max_total_bytes
+            .is_some_and(|max_total_bytes| self.total_bytes.get() >= max_total_bytes)
+    }
+
+    pub fn deadline_reached(&self) -> bool {
+        self.deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+    }
+
+    // Sleeps don't outlast the deadline
+    pub fn cap_time(&self, time: Duration) -> Duration {
+        match self.deadline {
+            Some(deadline) => time.min(deadline.saturating_duration_since(Instant::now())),
+            None => time,
+        }
+    }
+}
+
+pub fn run(
+    client: &Client,
+    config: &Config,
+    roots: &[String],
+    machine_config_path: &str,
+    logs: &Logs,
+    backoffs: &Backoffs,
+    budget: &Budget,
+) -> CrawlReport {
+    let now = Instant::now();
     let machine_config = parse_config(machine_config_path).expect("Failed to parse machine config");
 
     let urls: Vec<&String> = roots
         .iter()
-        .filter(|url| !value_in_blacklist(url, &machine_config.blacklist.roots))
+        .filter(|url| {
+            if value_in_blacklist(url, &machine_config.blacklist.roots) {
+                warn!("Root URL `{}` is in the blacklist, skipped", url);
+                false
+            } else {
+                true
+            }
+        })
         .collect();
 
     assert!(!urls.is_empty(), "Root URLs for crawling are empty");
 
+    let hosts_ports = RefCell::new(HashMap::new());
+    let hosts_requests = RefCell::new(HashMap::new());
+    let mut roots_stats = vec![];
+
     for url in urls {
-        match crawl(client, config, &machine_config, machine_config_path, url, 0) {
+        if budget.bytes_exceeded() || budget.deadline_reached() {
+            break;
+        }
+
+        let ctx = Context {
+            client,
+            config,
+            machine_config: &machine_config,
+            machine_config_path,
+            logs,
+            backoffs,
+            max_depth: get_max_depth(config),
+            root_url: url,
+            breadth: RefCell::new(HashMap::new()),
+            started: Instant::now(),
+            hosts_ports: &hosts_ports,
+            hosts_requests: &hosts_requests,
+            stats: RootStats::default(),
+            budget,
+            traps: RefCell::new(HashMap::new()),
+        };
+        debug!("Maximum depth for `{}`: {}", url, ctx.max_depth);
+
+        match crawl(&ctx, url, None, 0) {
             CrawlResult::Success => (),
             CrawlResult::Failure => info!("Failed to crawl the root URL: `{}`", url),
         }
+        roots_stats.push((url, ctx.stats));
+    }
+
+    let mut report = CrawlReport::default();
+    roots_stats.sort_by_key(|(_, stats)| Reverse(stats.requests.get()));
+    info!("Traffic of root URLs in the cycle:");
+    for (url, stats) in roots_stats {
+        info!(
+            "`{}`: {} requests, {} discovered URLs, {} bytes",
+            url,
+            stats.requests.get(),
+            stats.discovered_urls.get(),
+            stats.bytes.get()
+        );
+
+        report.requests += stats.requests.get();
+        report.failed_requests += stats.failed_requests.get();
+        report.discovered_urls += stats.discovered_urls.get();
+        report.bytes += stats.bytes.get();
     }
+    report.hosts_requests = hosts_requests.into_inner();
+    report.duration = now.elapsed();
+    report.deadline_reached = budget.deadline_reached();
+
+    report
 }
 
-fn crawl(
-    client: &Client,
-    config: &Config,
-    machine_config: &MachineConfig,
-    machine_config_path: &str,
-    url: &str,
-    current_depth: u32,
-) -> CrawlResult {
-    if current_depth >= config.client.max_depth {
+pub fn get_random_sleep(config: &Config) -> u32 {
+    thread_rng().sample(Uniform::new(
+        config.client.min_sleep,
+        config.client.max_sleep,
+    ))
+}
+
+// Pages can link to many ports of a host to make the crawler open many connections
+fn port_is_acceptable(ctx: &Context, url: &str) -> bool {
+    let max_distinct_ports = match ctx.config.urls.max_distinct_ports {
+        Some(max_distinct_ports) => max_distinct_ports,
+        None => return true,
+    };
+    let (host, port) = match Url::parse(url) {
+        Ok(url) => match (url.host_str(), url.port_or_known_default()) {
+            (Some(host), Some(port)) => (host.to_string(), port),
+            _ => return true,
+        },
+        Err(_) => return true,
+    };
+
+    let mut hosts_ports = ctx.hosts_ports.borrow_mut();
+    let ports = hosts_ports.entry(host).or_default();
+    if ports.contains(&port) {
+        true
+    } else if ports.len() >= max_distinct_ports {
+        debug!(
+            "Skip URL `{}`, its host has already got {} distinct ports",
+            url, max_distinct_ports
+        );
+        false
+    } else {
+        ports.insert(port);
+        true
+    }
+}
+
+// Counts a URL at the depth of the tree, `false` if the depth has already got the maximum breadth
+fn breadth_is_acceptable(ctx: &Context, depth: u32) -> bool {
+    let breadth_per_depth = match ctx.config.client.breadth_per_depth {
+        Some(breadth_per_depth) => breadth_per_depth,
+        None => return true,
+    };
+
+    let mut breadth = ctx.breadth.borrow_mut();
+    let depth_breadth = breadth.entry(depth).or_insert(0);
+    if *depth_breadth >= breadth_per_depth {
+        false
+    } else {
+        *depth_breadth += 1;
+        true
+    }
+}
+
+fn subtree_time_exceeded(ctx: &Context) -> bool {
+    ctx.config
+        .client
+        .max_subtree_duration_secs
+        .is_some_and(|secs| ctx.started.elapsed() >= Duration::from_secs(secs))
+}
+
+// Directory of the URL path, e.g. `https://example.com/a/b/` for `https://example.com/a/b/c`
+fn get_path_prefix(url: &str) -> Option<String> {
+    let mut url = Url::parse(url).ok()?;
+    url.set_query(None);
+    url.set_fragment(None);
+
+    let prefix_len = url.path().rfind('/')? + 1;
+    let path = url.path()[..prefix_len].to_string();
+    url.set_path(&path);
+
+    Some(url.into())
+}
+
+fn in_trap(ctx: &Context, url: &str) -> bool {
+    ctx.config.client.trap_detection.is_some()
+        && get_path_prefix(url).is_some_and(|prefix| {
+            ctx.traps
+                .borrow()
+                .get(&prefix)
+                .is_some_and(|sizes| sizes.trapped)
+        })
+}
+
+// Counts consecutive pages with near-identical sizes under the path prefix of the URL
+fn is_trap(ctx: &Context, url: &str, size: usize) -> bool {
+    let trap_detection = match &ctx.config.client.trap_detection {
+        Some(trap_detection) => trap_detection,
+        None => return false,
+    };
+    let prefix = match get_path_prefix(url) {
+        Some(prefix) => prefix,
+        None => return false,
+    };
+
+    let mut traps = ctx.traps.borrow_mut();
+    let sizes = traps.entry(prefix).or_default();
+
+    let difference = sizes.last_size.abs_diff(size) as f64;
+    if sizes.similar_pages > 0 && difference <= sizes.last_size as f64 * trap_detection.size_ratio {
+        sizes.similar_pages += 1;
+    } else {
+        sizes.similar_pages = 1;
+    }
+    sizes.last_size = size;
+    sizes.trapped = sizes.similar_pages >= trap_detection.max_similar_pages;
+
+    sizes.trapped
+}
+
+// Weighted shuffle: URLs are sorted by `random ^ (1 / weight)`, so heavier URLs tend to be first.
+// URLs with weight 0 are always last, they're followed only if all others fail
+fn shuffle_urls(config: &Config, urls: &mut Vec<String>) {
+    if config.urls.path_weights.is_empty() {
+        urls.shuffle(&mut thread_rng());
+        return;
+    }
+
+    let mut rng = thread_rng();
+    let mut keyed_urls: Vec<(f64, String)> = urls
+        .drain(..)
+        .map(|url| {
+            let weight = get_path_weight(config, &url);
+            let key = if weight == 0 {
+                0.0
+            } else {
+                rng.gen::<f64>().powf(1.0 / f64::from(weight))
+            };
+
+            (key, url)
+        })
+        .collect();
+    keyed_urls.sort_by(|(key, _), (other_key, _)| other_key.total_cmp(key));
+
+    urls.extend(keyed_urls.into_iter().map(|(_, url)| url));
+}
+
+fn get_path_weight(config: &Config, url: &str) -> u32 {
+    let path = match Url::parse(url) {
+        Ok(url) => url.path().to_string(),
+        Err(_) => return 1,
+    };
+
+    config
+        .urls
+        .path_weights
+        .iter()
+        .find(|path_weight| glob_match(&path_weight.glob, &path))
+        .map_or(1, |path_weight| path_weight.weight)
+}
+
+fn get_max_depth(config: &Config) -> u32 {
+    match config.client.depth_jitter {
+        Some(DepthJitter {
+            min_depth,
+            max_depth,
+        }) => thread_rng().gen_range(min_depth..=max_depth),
+        None => config.client.max_depth,
+    }
+}
+
+fn crawl(ctx: &Context, url: &str, parent_url: Option<&str>, current_depth: u32) -> CrawlResult {
+    let Context {
+        client,
+        config,
+        machine_config,
+        machine_config_path,
+        logs,
+        backoffs,
+        max_depth,
+        root_url,
+        ..
+    } = *ctx;
+
+    if current_depth >= max_depth {
         info!("Maximum depth reached");
 
+        return CrawlResult::Success;
+    } else if subtree_time_exceeded(ctx) {
+        info!(
+            "Maximum crawl time of `{}` reached at depth {}",
+            root_url, current_depth
+        );
+
+        return CrawlResult::Success;
+    } else if ctx.budget.bytes_exceeded() {
+        info!("Maximum total bytes reached at depth {}", current_depth);
+
         return CrawlResult::Success;
     } else if current_depth > 0 {
-        let time = thread_rng().sample(Uniform::new(
-            config.client.min_sleep,
-            config.client.max_sleep,
-        ));
+        let time = get_random_sleep(config);
         debug!(
             "Sleeps for {} seconds before starting a new one. Current depth: {}",
             time, current_depth
         );
-        thread_sleep(Duration::from_secs(u64::from(time)));
+        thread_sleep(ctx.budget.cap_time(Duration::from_secs(u64::from(time))));
+    }
+
+    if in_trap(ctx, url) {
+        info!("URL `{}` is under a suspected crawl trap, skipped", url);
+
+        return CrawlResult::Failure;
+    }
+
+    wait_if_paused(ctx.budget.deadline());
+    backoffs.wait(url, ctx.budget.deadline());
+
+    if ctx.budget.deadline_reached() {
+        info!("Hard deadline reached at depth {}", current_depth);
+
+        return CrawlResult::Success;
+    }
+
+    if let Some(host) = get_host(url) {
+        *ctx.hosts_requests.borrow_mut().entry(host).or_insert(0) += 1;
+    }
+
+    let method = client.get_random_method();
+    let now = Instant::now();
+    let result = client.request(method.clone(), url, parent_url, ctx.budget.deadline());
+    backoffs.update(url, &result);
+    ctx.stats.requests.set(ctx.stats.requests.get() + 1);
+    if result
+        .as_ref()
+        .map_or(true, |resp| resp.error_for_status_ref().is_err())
+    {
+        ctx.stats
+            .failed_requests
+            .set(ctx.stats.failed_requests.get() + 1);
+    }
+    // The edge is written once the child URL is requested, not when it's chosen to follow
+    if let (Some(graph_log), Some(parent_url)) = (&logs.graph, parent_url) {
+        graph_log
+            .write(&Edge {
+                parent_url,
+                url,
+                depth: current_depth,
+            })
+            .expect("Failed to write graph log edge");
+    }
+    if let Some(audit_log) = &logs.audit {
+        audit_log
+            .write(&Record::new(
+                url,
+                parent_url,
+                &method,
+                &result,
+                now.elapsed(),
+                current_depth,
+            ))
+            .expect("Failed to write audit log record");
     }
 
-    let resp = match client.get(url) {
+    let resp = match result {
         Ok(resp) => {
             if write_blacklist_url_if_need(
                 Some(&resp),
@@ -92,16 +503,61 @@ fn crawl(
             return CrawlResult::Failure;
         }
     };
+    if method != Method::GET {
+        // Only `GET` responses have got a body with links, so the branch ends here
+        return CrawlResult::Success;
+    }
+    if config.client.respect_x_robots_tag && is_x_robots_nofollow(&resp) {
+        info!("URL `{}` doesn't allow to follow its links", url);
+
+        // Like `HeaderHint::Stop`, the page isn't a failure, so it isn't blacklisted
+        return CrawlResult::Success;
+    }
+    for (name, hint) in &config.client.header_hints {
+        if !resp.headers().contains_key(name.as_str()) {
+            continue;
+        }
+
+        match hint {
+            HeaderHint::Stop => {
+                info!(
+                    "URL `{}` has got `{}` header, its links aren't followed",
+                    url, name
+                );
+
+                return CrawlResult::Success;
+            }
+            HeaderHint::Blacklist => {
+                info!("URL `{}` has got `{}` header, it's blacklisted", url, name);
+
+                let url = [url.to_string()];
+                if current_depth == 0 {
+                    write_blacklist_urls(machine_config_path, &url, &[], &[], &[])
+                } else {
+                    write_blacklist_urls(machine_config_path, &[], &url, &[], &[])
+                }
+                .expect("Failed to write blacklist URL");
+
+                return CrawlResult::Failure;
+            }
+        }
+    }
     let new_url = resp.url().clone();
+    let content_type = get_content_type(&resp);
+    let refresh = get_refresh_href(&resp);
 
     let now = Instant::now();
-    let html = match resp.text() {
+    let html = match read_text(resp, config.client.max_body_size) {
         Ok(html) => html,
         Err(err) => {
             info!("Couldn't get HTML from URL `{}`: {}", url, err);
             return CrawlResult::Failure;
         }
     };
+    ctx.stats
+        .bytes
+        .set(ctx.stats.bytes.get() + html.len() as u64);
+    ctx.budget.add_bytes(html.len() as u64);
     debug!(
         "The HTML parsing took {} seconds. Length of text and lines: {}, {}",
         now.elapsed().as_secs_f32(),
@@ -109,35 +565,70 @@ fn crawl(
         html.lines().count(),
     );
 
-    let dom = parse_dom(&html).expect("Failed to parse DOM");
-    let mut hrefs = get_hrefs(
-        &dom,
-        &machine_config.blacklist.hrefs,
-        &machine_config.blacklist.types,
-    );
-    if hrefs.is_empty() {
+    if is_trap(ctx, new_url.as_str(), html.len()) {
+        info!(
+            "Pages under the path of `{}` have near-identical sizes, suspected crawl trap",
+            new_url
+        );
+
+        return CrawlResult::Failure;
+    }
+
+    let mut urls = match get_page_urls(
+        config,
+        machine_config,
+        &new_url,
+        &html,
+        content_type.as_deref(),
+        refresh.as_deref(),
+        root_url,
+    ) {
+        Some(urls) => urls,
+        // Meta robots don't allow to follow the links, it isn't a failure like with X-Robots-Tag
+        None => return CrawlResult::Success,
+    };
+    ctx.stats
+        .discovered_urls
+        .set(ctx.stats.discovered_urls.get() + urls.len() as u32);
+    if urls.is_empty() {
         return CrawlResult::Failure;
     }
 
-    hrefs.shuffle(&mut thread_rng());
+    urls.retain(|_| thread_rng().gen_bool(config.client.follow_probability));
+    if urls.is_empty() {
+        // The page has got links, so it isn't a failure
+        debug!("No child URLs of `{}` are chosen to follow", new_url);
+
+        return CrawlResult::Success;
+    }
+
+    shuffle_urls(config, &mut urls);
+    if config.client.prefer_new_hosts {
+        // Sorting is stable, so URLs stay shuffled among hosts crawled or not in the cycle
+        let hosts_requests = ctx.hosts_requests.borrow();
+        urls.sort_by_key(|url| {
+            get_host(url).is_some_and(|host| hosts_requests.contains_key(&host))
+        });
+    }
 
     let mut result = CrawlResult::Failure;
     let mut failure_urls = vec![];
     let mut failure_urls_len: u32 = 0;
-    for href in hrefs {
-        let url = match get_url(new_url.as_str(), href, &machine_config.blacklist.childs) {
-            Some(url) => url,
-            None => continue,
-        };
-
-        match crawl(
-            client,
-            config,
-            machine_config,
-            machine_config_path,
-            &url,
-            current_depth + 1,
-        ) {
+    for url in urls {
+        if !port_is_acceptable(ctx, &url) {
+            continue;
+        }
+        if !breadth_is_acceptable(ctx, current_depth + 1) {
+            info!(
+                "Maximum breadth reached at depth {}, stopped crawling `{}` child URLs",
+                current_depth + 1,
+                new_url
+            );
+            // Child URLs are left by the limit, not failed, so the page isn't blacklisted
+            result = CrawlResult::Success;
+            break;
+        }
+        match crawl(ctx, &url, Some(new_url.as_str()), current_depth + 1) {
             CrawlResult::Success => {
                 result = CrawlResult::Success;
                 break;
@@ -152,10 +643,970 @@ fn crawl(
             }
         }
     }
-    if !failure_urls.is_empty() {
+    if failure_urls.is_empty() {
+        // No child URLs failed, they were skipped by the ports limit, so the page isn't a failure
+        result = CrawlResult::Success;
+    } else {
         write_blacklist_urls(machine_config_path, &[], &failure_urls, &[], &[])
             .expect("Failed to write blacklist URLs");
     }
 
     result
 }
+
+// Media type without parameters, `text/html` for `text/html; charset=utf-8`
+pub fn get_content_type(response: &Response) -> Option<String> {
+    response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .map(|media_type| media_type.trim().to_string())
+}
+
+pub fn get_refresh_href(response: &Response) -> Option<String> {
+    response
+        .headers()
+        .get(REFRESH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(parse_refresh)
+        .map(ToString::to_string)
+}
+
+pub fn is_x_robots_nofollow(response: &Response) -> bool {
+    response
+        .headers()
+        .get_all(X_ROBOTS_TAG)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .any(is_nofollow_directive)
+}
+
+// Hrefs of the page with their child URLs or reasons of rejecting them,
+// `None` if the page doesn't allow to follow its links
+pub fn get_page_hrefs(
+    config: &Config,
+    machine_config: &MachineConfig,
+    url: &Url,
+    html: &str,
+    content_type: Option<&str>,
+    refresh: Option<&str>,
+    root_url: &str,
+) -> Option<Vec<(String, Result<String, RejectReason>)>> {
+    let is_feed = content_type.is_some_and(|content_type| {
+        config
+            .client
+            .feed_types
+            .iter()
+            .any(|feed_type| feed_type.eq_ignore_ascii_case(content_type))
+    });
+    // `application/json`, `application/ld+json` and so on
+    let json = if config.client.scan_json
+        && content_type.is_some_and(|content_type| content_type.ends_with("json"))
+    {
+        match serde_json::from_str::<JsonValue>(html) {
+            Ok(json) => Some(json),
+            Err(err) => {
+                debug!("Failed to parse JSON of `{}`: {}", url, err);
+                return Some(vec![]);
+            }
+        }
+    } else {
+        None
+    };
+    let dom = if is_feed || json.is_some() || config.client.streaming_parser {
+        None
+    } else if config
+        .client
+        .max_dom_size
+        .is_some_and(|max_dom_size| html.len() as u64 > max_dom_size)
+    {
+        // Hostile pages can make building the DOM slow and memory-hungry, scanning is linear
+        debug!(
+            "Body of `{}` with length {} is too large for DOM, hrefs are scanned instead",
+            url,
+            html.len()
+        );
+        None
+    } else {
+        match parse_dom(html) {
+            Ok(dom) => Some(dom),
+            Err(err) => {
+                // `tl` fails only on bodies longer than `u32::MAX`, so the hrefs are scanned instead
+                debug!(
+                    "Failed to parse DOM of `{}` with body length {}: {}",
+                    url,
+                    html.len(),
+                    err
+                );
+                None
+            }
+        }
+    };
+    if let Some(dom) = &dom {
+        if config.soft_404.detect && is_soft_404(dom, &config.soft_404.patterns) {
+            info!("URL `{}` looks like a soft 404 page", url);
+
+            return Some(vec![]);
+        }
+        if config.client.respect_meta_robots && is_nofollow(dom) {
+            info!("URL `{}` doesn't allow to follow its links", url);
+
+            return None;
+        }
+    }
+    let page_url = match (&dom, config.client.use_canonical) {
+        (Some(dom), true) => match get_canonical_url(dom) {
+            Some(canonical_url) => {
+                debug!("Found canonical URL `{}` for `{}`", canonical_url, url);
+
+                get_url_from_href(url.as_str(), canonical_url)
+            }
+            None => url.to_string(),
+        },
+        _ => url.to_string(),
+    };
+    let mut hrefs = match (&dom, &json) {
+        (None, Some(json)) => get_json_hrefs(json),
+        (None, None) if is_feed => get_feed_hrefs(html),
+        (Some(dom), _) => {
+            let hrefs = get_hrefs(dom, config.client.content_selector.as_deref());
+            if hrefs.is_empty() {
+                // Malformed markup can make the DOM lose tags, while scanning still finds them
+                debug!("No hrefs in DOM of `{}`, hrefs are scanned instead", url);
+
+                // Comments are scanned below
+                scan_hrefs(html, false)
+            } else {
+                hrefs
+            }
+        }
+        (None, None) => scan_hrefs(html, config.client.scan_comments),
+    };
+    if dom.is_some() && config.client.scan_comments {
+        hrefs.extend(scan_comments_hrefs(html));
+    }
+
+    if let Some(refresh) = refresh {
+        debug!("Found `Refresh` header URL `{}` for `{}`", refresh, url);
+
+        hrefs.push(refresh);
+    }
+
+    let normalizer = UrlNormalizer::new(&config.urls.normalize);
+    let self_urls = [url.as_str(), &page_url].map(|url| normalizer.normalize(url).ok());
+
+    let page_hrefs = hrefs
+        .into_iter()
+        .map(|href| {
+            let result = check_href_acceptable(
+                href,
+                &machine_config.blacklist.hrefs,
+                &machine_config.blacklist.types,
+            )
+            .and_then(|()| {
+                get_url(
+                    &page_url,
+                    href,
+                    &machine_config.blacklist.childs,
+                    &config.urls,
+                    root_url,
+                )
+            })
+            .and_then(|child_url| {
+                if self_urls.iter().flatten().any(|url| *url == child_url) {
+                    Err(RejectReason::SelfLink)
+                } else {
+                    Ok(child_url)
+                }
+            });
+
+            (href.to_string(), result)
+        })
+        .collect();
+
+    Some(page_hrefs)
+}
+
+// Child URLs of the page, `None` if it doesn't allow to follow its links
+pub fn get_page_urls(
+    config: &Config,
+    machine_config: &MachineConfig,
+    url: &Url,
+    html: &str,
+    content_type: Option<&str>,
+    refresh: Option<&str>,
+    root_url: &str,
+) -> Option<Vec<String>> {
+    let page_hrefs = get_page_hrefs(
+        config,
+        machine_config,
+        url,
+        html,
+        content_type,
+        refresh,
+        root_url,
+    )?;
+
+    let urls = page_hrefs
+        .into_iter()
+        .filter_map(|(href, result)| match result {
+            Ok(child_url) => Some(child_url),
+            Err(reason) => {
+                debug!("Skip href `{}`: {}", href, reason);
+                None
+            }
+        })
+        .collect();
+
+    Some(urls)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config_reader::{Methods, PathWeight, TrapDetection},
+        machine_config::{create_config, BlacklistUrls},
+    };
+    use std::{
+        fs,
+        io::{Read, Write},
+        net::TcpListener,
+    };
+
+    fn config() -> Config {
+        serde_yaml::from_str(include_str!("../config.yaml")).unwrap()
+    }
+
+    fn machine_config() -> MachineConfig {
+        MachineConfig {
+            blacklist: BlacklistUrls {
+                roots: vec![],
+                childs: vec![],
+                hrefs: vec![],
+                types: vec![],
+            },
+        }
+    }
+
+    // Runs the function with a context of a root URL, nothing is crawled
+    fn with_context<T>(config: &Config, f: impl FnOnce(&Context) -> T) -> T {
+        let client = Client::new(&config.client, &config.user_agent, &config.urls);
+        let machine_config = machine_config();
+        let logs = Logs {
+            audit: None,
+            graph: None,
+        };
+        let backoffs = Backoffs::new(&config.client.backoff);
+        let budget = Budget::new(config);
+        let hosts_ports = RefCell::new(HashMap::new());
+        let hosts_requests = RefCell::new(HashMap::new());
+
+        f(&Context {
+            client: &client,
+            config,
+            machine_config: &machine_config,
+            machine_config_path: "machine_config.json",
+            logs: &logs,
+            backoffs: &backoffs,
+            max_depth: config.client.max_depth,
+            root_url: "https://example.com/",
+            breadth: RefCell::new(HashMap::new()),
+            started: Instant::now(),
+            hosts_ports: &hosts_ports,
+            hosts_requests: &hosts_requests,
+            stats: RootStats::default(),
+            budget: &budget,
+            traps: RefCell::new(HashMap::new()),
+        })
+    }
+
+    fn page_urls(config: &Config, url: &str, html: &str, content_type: &str) -> Vec<String> {
+        get_page_urls(
+            config,
+            &machine_config(),
+            &Url::parse(url).unwrap(),
+            html,
+            Some(content_type),
+            None,
+            url,
+        )
+        .unwrap_or_default()
+    }
+
+    // Answers requests by their paths until the test ends, other paths are `404 Not Found`
+    fn serve_site(pages: Vec<(&'static str, String)>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut buf = [0; 4096];
+                let len = stream.read(&mut buf).unwrap();
+                let request = String::from_utf8_lossy(&buf[..len]);
+                let path = request.split(' ').nth(1).unwrap_or_default();
+
+                let response = pages
+                    .iter()
+                    .find(|(page_path, _)| *page_path == path)
+                    .map_or_else(
+                        || page("404 Not Found", "", ""),
+                        |(_, response)| response.clone(),
+                    );
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+        });
+
+        url
+    }
+
+    fn page(status: &str, headers: &str, body: &str) -> String {
+        format!(
+            "HTTP/1.1 {}\r\n{}Content-Type: text/html\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            headers,
+            body.len(),
+            body
+        )
+    }
+
+    // Crawls the root URLs with only `GET` requests and no sleeps,
+    // returns the machine config, the graph log and the report
+    fn crawl_site(
+        config: &mut Config,
+        root_urls: &[&str],
+        name: &str,
+    ) -> (MachineConfig, String, CrawlReport) {
+        let temp_path = |suffix: &str| {
+            std::env::temp_dir()
+                .join(format!(
+                    "noisy_web_traffic_{}_{}_{}",
+                    std::process::id(),
+                    name,
+                    suffix
+                ))
+                .to_str()
+                .unwrap()
+                .to_string()
+        };
+        let machine_config_path = temp_path("machine_config.json");
+        let graph_path = temp_path("graph.jsonl");
+
+        config.client.min_sleep = 0;
+        config.client.max_sleep = 1;
+        config.client.methods = Methods {
+            get: 1,
+            head: 0,
+            options: 0,
+        };
+        config.logs.graph = Some(graph_path.clone());
+        create_config(&machine_config_path).unwrap();
+
+        let client = Client::new(&config.client, &config.user_agent, &config.urls);
+        let logs = Logs::new(&config.logs).unwrap();
+        let backoffs = Backoffs::new(&config.client.backoff);
+        let budget = Budget::new(config);
+        let root_urls: Vec<String> = root_urls.iter().map(ToString::to_string).collect();
+        let report = run(
+            &client,
+            config,
+            &root_urls,
+            &machine_config_path,
+            &logs,
+            &backoffs,
+            &budget,
+        );
+
+        let machine_config = parse_config(&machine_config_path).unwrap();
+        let graph = fs::read_to_string(&graph_path).unwrap();
+        fs::remove_file(machine_config_path).unwrap();
+        fs::remove_file(graph_path).unwrap();
+
+        (machine_config, graph, report)
+    }
+
+    #[test]
+    fn crawl_report() {
+        let mut config = config();
+        let root = r#"<a href="/missing">Missing</a><a href="/a">A</a>"#;
+        let url = serve_site(vec![
+            ("/", page("200 OK", "", root)),
+            ("/a", page("200 OK", "", "")),
+        ]);
+
+        let (machine_config, _, report) = crawl_site(&mut config, &[&url], "crawl_report");
+        // Both child URLs are crawled in any order and fail: `/missing` is 404, `/a` has got no links
+        assert_eq!(report.requests, 3);
+        assert_eq!(report.failed_requests, 1);
+        assert_eq!(report.discovered_urls, 2);
+        assert_eq!(report.bytes, root.len() as u64);
+        assert_eq!(
+            report.hosts_requests,
+            HashMap::from([("127.0.0.1".to_string(), 3)])
+        );
+        assert!(!report.deadline_reached);
+        assert_eq!(machine_config.blacklist.childs.len(), 2);
+    }
+
+    #[test]
+    fn traffic_attributed_to_roots() {
+        let mut config = config();
+        let first_url = serve_site(vec![
+            ("/", page("200 OK", "", r#"<a href="/a">A</a>"#)),
+            ("/a", page("200 OK", "", r#"<a href="/b">B</a>"#)),
+        ]);
+        // Another host name, so the requests of the roots are told apart
+        let second_url =
+            serve_site(vec![("/", page("200 OK", "", ""))]).replace("127.0.0.1", "localhost");
+
+        let (_, _, report) = crawl_site(&mut config, &[&first_url, &second_url], "roots_traffic");
+        assert_eq!(
+            report.hosts_requests,
+            HashMap::from([("127.0.0.1".to_string(), 3), ("localhost".to_string(), 1)])
+        );
+        assert_eq!(report.requests, 4);
+    }
+
+    #[test]
+    fn graph_edges_of_requested_urls() {
+        let mut config = config();
+        config.client.max_depth = 2;
+        config.client.depth_jitter = None;
+
+        let url = serve_site(vec![
+            ("/", page("200 OK", "", r#"<a href="/a">A</a>"#)),
+            ("/a", page("200 OK", "", r#"<a href="/b">B</a>"#)),
+            ("/b", page("200 OK", "", r#"<a href="/c">C</a>"#)),
+        ]);
+
+        // `/b` is at the maximum depth, so it isn't requested
+        let (_, graph, _) = crawl_site(&mut config, &[&url], "graph_edges");
+        assert_eq!(
+            graph,
+            format!(
+                "{{\"parent_url\":\"{}/\",\"url\":\"{}/a\",\"depth\":1}}\n",
+                url, url
+            )
+        );
+    }
+
+    #[test]
+    fn follow_probability() {
+        let mut config = config();
+        let url = serve_site(vec![
+            ("/", page("200 OK", "", r#"<a href="/a">A</a>"#)),
+            ("/a", page("200 OK", "", "")),
+        ]);
+
+        config.client.follow_probability = 0.0;
+        let (_, graph, _) = crawl_site(&mut config, &[&url], "follow_probability_0");
+        assert!(graph.is_empty());
+
+        config.client.follow_probability = 1.0;
+        let (_, graph, _) = crawl_site(&mut config, &[&url], "follow_probability_1");
+        assert!(
+            graph.contains(&format!("\"url\":\"{}/a\"", url)),
+            "{}",
+            graph
+        );
+    }
+
+    #[test]
+    fn nofollow_pages_not_blacklisted() {
+        let root = r#"<a href="/child">Child</a>"#;
+        let meta_robots =
+            r#"<head><meta name="robots" content="nofollow"></head><a href="/a">A</a>"#;
+
+        for (name, child) in [
+            (
+                "x_robots_tag",
+                page(
+                    "200 OK",
+                    "X-Robots-Tag: nofollow\r\n",
+                    r#"<a href="/a">A</a>"#,
+                ),
+            ),
+            ("meta_robots", page("200 OK", "", meta_robots)),
+        ] {
+            let url = serve_site(vec![("/", page("200 OK", "", root)), ("/child", child)]);
+
+            let (machine_config, _, _) = crawl_site(&mut config(), &[&url], name);
+            assert!(
+                machine_config.blacklist.childs.is_empty(),
+                "{}: {:?}",
+                name,
+                machine_config.blacklist.childs
+            );
+        }
+    }
+
+    #[test]
+    fn self_links_skipped() {
+        let mut config = config();
+        config.urls.normalize.strip_fragment = true;
+        // All of them pass the href checks, but resolve to the page itself
+        let html = r#"<a href="https://example.com/a/b">A</a><a href="/a/b#top">B</a>
+            <a href="/a/b?utm_source=feed">C</a><a href="/c">D</a>"#;
+        let url = Url::parse("https://example.com/a/b").unwrap();
+
+        let page_hrefs = get_page_hrefs(
+            &config,
+            &machine_config(),
+            &url,
+            html,
+            Some("text/html"),
+            None,
+            url.as_str(),
+        )
+        .unwrap();
+        assert_eq!(
+            page_hrefs,
+            [
+                (
+                    "https://example.com/a/b".to_string(),
+                    Err(RejectReason::SelfLink)
+                ),
+                ("/a/b#top".to_string(), Err(RejectReason::SelfLink)),
+                (
+                    "/a/b?utm_source=feed".to_string(),
+                    Err(RejectReason::SelfLink)
+                ),
+                ("/c".to_string(), Ok("https://example.com/c".to_string())),
+            ]
+        );
+    }
+
+    #[test]
+    fn page_hrefs_reject_reasons() {
+        let machine_config = MachineConfig {
+            blacklist: BlacklistUrls {
+                roots: vec![],
+                childs: vec!["https://example.com/hidden".to_string()],
+                hrefs: vec!["/private".to_string()],
+                types: vec!["pdf".to_string()],
+            },
+        };
+        let html = r#"<a href="mailto:a@example.com">A</a><a href="/file.pdf">B</a>
+            <a href="/private/c">C</a><a href="/hidden/d">D</a><a href="/a#top">E</a>
+            <a href="/f">F</a>"#;
+        let url = Url::parse("https://example.com/a").unwrap();
+
+        let page_hrefs = get_page_hrefs(
+            &config(),
+            &machine_config,
+            &url,
+            html,
+            Some("text/html"),
+            None,
+            url.as_str(),
+        )
+        .unwrap();
+        assert_eq!(
+            page_hrefs,
+            [
+                (
+                    "mailto:a@example.com".to_string(),
+                    Err(RejectReason::MalformedHref)
+                ),
+                (
+                    "/file.pdf".to_string(),
+                    Err(RejectReason::BlacklistedType {
+                        media_type: "pdf".to_string()
+                    })
+                ),
+                ("/private/c".to_string(), Err(RejectReason::BlacklistedHref)),
+                (
+                    "/hidden/d".to_string(),
+                    Err(RejectReason::Blacklisted {
+                        prefix: "https://example.com/hidden".to_string()
+                    })
+                ),
+                ("/a#top".to_string(), Err(RejectReason::SelfLink)),
+                ("/f".to_string(), Ok("https://example.com/f".to_string())),
+            ]
+        );
+    }
+
+    #[test]
+    fn hrefs_resolved_against_canonical_url() {
+        let mut config = config();
+        // Only root-relative and absolute hrefs are followed, so the canonical URL matters by its origin
+        let html = r#"<head><link rel="canonical" href="https://www.example.com/a"></head>
+            <a href="/b">B</a>"#;
+
+        config.client.use_canonical = true;
+        assert_eq!(
+            page_urls(&config, "https://example.com/x?y=1", html, "text/html"),
+            ["https://www.example.com/b"]
+        );
+
+        config.client.use_canonical = false;
+        assert_eq!(
+            page_urls(&config, "https://example.com/x?y=1", html, "text/html"),
+            ["https://example.com/b"]
+        );
+    }
+
+    #[test]
+    fn content_selector() {
+        let mut config = config();
+        let html = r#"<nav><a href="/nav">Nav</a></nav>
+            <main><a href="/a">A</a><div><a href="/b">B</a></div></main>
+            <footer><a href="/footer">Footer</a></footer>"#;
+
+        config.client.content_selector = Some("main".to_string());
+        assert_eq!(
+            page_urls(&config, "https://example.com/", html, "text/html"),
+            ["https://example.com/a", "https://example.com/b"]
+        );
+
+        // The selector doesn't match anything, so the whole document is used
+        config.client.content_selector = Some("article".to_string());
+        assert_eq!(
+            page_urls(&config, "https://example.com/", html, "text/html"),
+            [
+                "https://example.com/nav",
+                "https://example.com/a",
+                "https://example.com/b",
+                "https://example.com/footer"
+            ]
+        );
+    }
+
+    #[test]
+    fn comments_scanned() {
+        let mut config = config();
+        let html = r#"<a href="/a">A</a><!-- <a href="/hidden">B</a> -->"#;
+
+        for streaming_parser in [false, true] {
+            config.client.streaming_parser = streaming_parser;
+
+            config.client.scan_comments = false;
+            assert_eq!(
+                page_urls(&config, "https://example.com/", html, "text/html"),
+                ["https://example.com/a"]
+            );
+
+            config.client.scan_comments = true;
+            let mut urls = page_urls(&config, "https://example.com/", html, "text/html");
+            urls.sort();
+            assert_eq!(
+                urls,
+                ["https://example.com/a", "https://example.com/hidden"]
+            );
+        }
+    }
+
+    fn response(headers: &[(&str, &str)]) -> Response {
+        let mut builder = http::Response::builder();
+        for (name, value) in headers {
+            builder = builder.header(*name, *value);
+        }
+
+        builder.body(Vec::<u8>::new()).unwrap().into()
+    }
+
+    #[test]
+    fn x_robots_tag() {
+        for value in ["nofollow", "noindex, nofollow", "NONE"] {
+            assert!(
+                is_x_robots_nofollow(&response(&[("X-Robots-Tag", value)])),
+                "{}",
+                value
+            );
+        }
+        assert!(is_x_robots_nofollow(&response(&[
+            ("X-Robots-Tag", "noarchive"),
+            ("X-Robots-Tag", "nofollow"),
+        ])));
+        assert!(!is_x_robots_nofollow(&response(&[(
+            "X-Robots-Tag",
+            "noindex"
+        )])));
+        assert!(!is_x_robots_nofollow(&response(&[])));
+    }
+
+    #[test]
+    fn refresh_header() {
+        assert_eq!(
+            get_refresh_href(&response(&[("Refresh", "5; url=https://example.com/a")])).as_deref(),
+            Some("https://example.com/a")
+        );
+        assert_eq!(get_refresh_href(&response(&[("Refresh", "5")])), None);
+        assert_eq!(
+            get_content_type(&response(&[("Content-Type", "text/html; charset=utf-8")])).as_deref(),
+            Some("text/html")
+        );
+    }
+
+    #[test]
+    fn refresh_child_url() {
+        let config = config();
+        let machine_config = MachineConfig {
+            blacklist: BlacklistUrls {
+                roots: vec![],
+                childs: vec![],
+                hrefs: vec![],
+                types: vec!["pdf".to_string()],
+            },
+        };
+        let url = Url::parse("https://example.com/").unwrap();
+        let refresh_urls = |refresh| {
+            get_page_urls(
+                &config,
+                &machine_config,
+                &url,
+                "<html></html>",
+                Some("text/html"),
+                Some(refresh),
+                url.as_str(),
+            )
+            .unwrap()
+        };
+
+        assert_eq!(refresh_urls("/a"), ["https://example.com/a"]);
+        assert!(refresh_urls("/file.pdf").is_empty());
+        assert!(refresh_urls("ftp://example.com/a").is_empty());
+    }
+
+    #[test]
+    fn large_body_scanned_instead_of_dom() {
+        let mut config = config();
+        config.client.max_dom_size = Some(1024);
+        // Deeply nested unclosed tags with an unterminated attribute are costly to build the DOM of
+        let html = format!(
+            r#"{}<a href="/a">A</a>{}<a href="/b"#,
+            "<div><span>".repeat(100_000),
+            "<p class=\"".repeat(10_000)
+        );
+
+        let now = Instant::now();
+        let urls = page_urls(&config, "https://example.com/", &html, "text/html");
+        assert_eq!(urls, ["https://example.com/a"]);
+        assert!(now.elapsed() < Duration::from_secs(10));
+    }
+
+    #[test]
+    fn malformed_dom_scanned() {
+        let urls = page_urls(
+            &config(),
+            "https://example.com/",
+            r#"<div <<<< <a href="/b">B</a>"#,
+            "text/html",
+        );
+        assert_eq!(urls, ["https://example.com/b"]);
+    }
+
+    #[test]
+    fn meta_robots() {
+        let mut config = config();
+        config.client.respect_meta_robots = true;
+        let html = |content: &str| {
+            format!(
+                r#"<html><head><meta name="robots" content="{}"></head><body><a href="/a">A</a></body></html>"#,
+                content
+            )
+        };
+
+        for content in ["nofollow", "noindex, nofollow", "NoFollow", "none"] {
+            assert!(
+                page_urls(&config, "https://example.com/", &html(content), "text/html").is_empty(),
+                "{}",
+                content
+            );
+        }
+        assert_eq!(
+            page_urls(
+                &config,
+                "https://example.com/",
+                &html("noindex"),
+                "text/html"
+            ),
+            ["https://example.com/a"]
+        );
+
+        config.client.respect_meta_robots = false;
+        assert_eq!(
+            page_urls(
+                &config,
+                "https://example.com/",
+                &html("nofollow"),
+                "text/html"
+            ),
+            ["https://example.com/a"]
+        );
+    }
+
+    #[test]
+    fn distinct_ports_capped() {
+        let mut config = config();
+        config.urls.max_distinct_ports = Some(2);
+
+        with_context(&config, |ctx| {
+            assert!(port_is_acceptable(ctx, "https://example.com:8443/a"));
+            assert!(port_is_acceptable(ctx, "https://example.com/a"));
+            assert!(!port_is_acceptable(ctx, "https://example.com:8444/a"));
+            assert!(!port_is_acceptable(ctx, "http://example.com/a"));
+            // Ports already crawled and other hosts are still acceptable
+            assert!(port_is_acceptable(ctx, "https://example.com:443/b"));
+            assert!(port_is_acceptable(ctx, "https://example.com:8443/b"));
+            assert!(port_is_acceptable(ctx, "https://example.org:8444/a"));
+        });
+    }
+
+    #[test]
+    fn breadth_capped_per_depth() {
+        let mut config = config();
+        config.client.breadth_per_depth = Some(2);
+
+        with_context(&config, |ctx| {
+            assert!(breadth_is_acceptable(ctx, 1));
+            assert!(breadth_is_acceptable(ctx, 1));
+            assert!(!breadth_is_acceptable(ctx, 1));
+            // Other depths have got their own breadth
+            assert!(breadth_is_acceptable(ctx, 2));
+            assert!(breadth_is_acceptable(ctx, 2));
+            assert!(!breadth_is_acceptable(ctx, 2));
+        });
+
+        config.client.breadth_per_depth = None;
+        with_context(&config, |ctx| {
+            assert!((0..100).all(|_| breadth_is_acceptable(ctx, 1)));
+        });
+    }
+
+    #[test]
+    fn weighted_shuffle() {
+        let mut config = config();
+        config.urls.path_weights = vec![
+            PathWeight {
+                glob: "/hot/*".to_string(),
+                weight: 50,
+            },
+            PathWeight {
+                glob: "/cold/*".to_string(),
+                weight: 0,
+            },
+        ];
+        let urls: Vec<String> = ["/a", "/cold/1", "/b", "/hot/1", "/c"]
+            .into_iter()
+            .map(|path| format!("https://example.com{}", path))
+            .collect();
+
+        let mut hot_first = 0;
+        for _ in 0..1000 {
+            let mut shuffled = urls.clone();
+            shuffle_urls(&config, &mut shuffled);
+
+            if shuffled[0] == "https://example.com/hot/1" {
+                hot_first += 1;
+            }
+            assert_eq!(shuffled[4], "https://example.com/cold/1");
+        }
+        // About 50 / 53 of shuffles, a uniform shuffle would give about 1 / 4
+        assert!(hot_first > 800, "{}", hot_first);
+    }
+
+    #[test]
+    fn total_bytes_capped() {
+        let mut config = config();
+        config.client.max_total_bytes = Some(100);
+
+        let budget = Budget::new(&config);
+        budget.add_bytes(60);
+        assert!(!budget.bytes_exceeded());
+        budget.add_bytes(40);
+        assert!(budget.bytes_exceeded());
+        assert_eq!(budget.total_bytes(), 100);
+
+        // Nothing is requested once the budget is exceeded
+        with_context(&config, |ctx| {
+            ctx.budget.add_bytes(100);
+
+            assert!(matches!(
+                crawl(ctx, "http://127.0.0.1:1/", None, 0),
+                CrawlResult::Success
+            ));
+            assert_eq!(ctx.stats.requests.get(), 0);
+        });
+
+        config.client.max_total_bytes = None;
+        let budget = Budget::new(&config);
+        budget.add_bytes(u64::from(u32::MAX));
+        assert!(!budget.bytes_exceeded());
+    }
+
+    #[test]
+    fn trap_detected() {
+        let mut config = config();
+        config.client.trap_detection = Some(TrapDetection {
+            max_similar_pages: 3,
+            size_ratio: 0.02,
+        });
+
+        with_context(&config, |ctx| {
+            assert!(!is_trap(ctx, "https://example.com/a/1", 1000));
+            assert!(!is_trap(ctx, "https://example.com/a/2", 1010));
+            assert!(!in_trap(ctx, "https://example.com/a/3"));
+            assert!(is_trap(ctx, "https://example.com/a/3", 1005));
+
+            assert!(in_trap(ctx, "https://example.com/a/4?page=4"));
+            assert!(!in_trap(ctx, "https://example.com/b/1"));
+            assert!(!in_trap(ctx, "https://example.com/a/b/1"));
+        });
+    }
+
+    #[test]
+    fn trap_not_detected_for_different_sizes() {
+        let mut config = config();
+        config.client.trap_detection = Some(TrapDetection {
+            max_similar_pages: 3,
+            size_ratio: 0.02,
+        });
+
+        with_context(&config, |ctx| {
+            for (page, size) in [1000, 2000, 1000, 1500, 3000].into_iter().enumerate() {
+                let url = format!("https://example.com/a/{}", page);
+
+                assert!(!is_trap(ctx, &url, size), "{}", url);
+            }
+            assert!(!in_trap(ctx, "https://example.com/a/5"));
+        });
+
+        config.client.trap_detection = None;
+        with_context(&config, |ctx| {
+            for page in 0..10 {
+                assert!(!is_trap(
+                    ctx,
+                    &format!("https://example.com/a/{}", page),
+                    1000
+                ));
+            }
+            assert!(!in_trap(ctx, "https://example.com/a/10"));
+        });
+    }
+
+    #[test]
+    fn max_depth_with_jitter() {
+        let mut config = config();
+        config.client.depth_jitter = Some(DepthJitter {
+            min_depth: 3,
+            max_depth: 7,
+        });
+
+        let depths: HashSet<u32> = (0..1000).map(|_| get_max_depth(&config)).collect();
+        assert!(depths.iter().all(|depth| (3..=7).contains(depth)));
+        assert!(depths.len() > 1);
+    }
+
+    #[test]
+    fn max_depth_without_jitter() {
+        let mut config = config();
+        config.client.depth_jitter = None;
+        config.client.max_depth = 4;
+
+        assert!((0..100).all(|_| get_max_depth(&config) == 4));
+    }
+}
@@ -74,7 +74,7 @@ pub fn write_blacklist_urls(
     File::options()
         .write(true)
         .truncate(true)
-        .open(&Path::new(path))?
+        .open(Path::new(path))?
         .write_all(json.as_bytes())?;
 
     Ok(config)
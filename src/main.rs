@@ -1,20 +1,49 @@
+mod audit;
+mod backoff;
 mod client;
+mod commands;
 mod config_reader;
 mod crawl;
 mod logger;
 mod machine_config;
 mod parser;
+mod pause;
+mod url_utils;
 
+use audit::Logs;
+use backoff::Backoffs;
 use client::Client;
 use config_reader::parse_config;
-use log::info;
-use machine_config::{create_config, write_blacklist_urls};
+use crawl::Budget;
+use log::{debug, info, warn};
+use machine_config::{create_config, parse_config as parse_machine_config, write_blacklist_urls};
 use rand::{seq::SliceRandom, thread_rng};
+use std::{
+    cmp::Reverse,
+    env, thread,
+    time::{Duration, Instant},
+};
 
 fn main() {
     logger::init();
 
     let config = parse_config("./config.yaml").expect("Failed to parse config");
+    for lint in config.lint() {
+        warn!("{}", lint);
+    }
+
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.iter().any(|arg| arg == "--print-effective-config") {
+        commands::print_effective_config(&config);
+        return;
+    }
+    if let Some(count) = get_arg_value(&args, "--sample-delays") {
+        commands::sample_delays(
+            &config,
+            count.parse().expect("Invalid number of delay samples"),
+        );
+        return;
+    }
 
     let machine_config_path = format!("./{}.json", config.machine_config.name);
 
@@ -29,18 +58,150 @@ fn main() {
     )
     .expect("Failed to write blacklist URLs");
 
-    let client = Client::new(
-        config.client.max_timeout,
-        config.client.max_redirections,
-        &config.user_agent.current,
-        config.user_agent.generate,
-    );
+    if let Some(count) = get_arg_value(&args, "--bench-matching") {
+        commands::bench_matching(
+            &config,
+            &parse_machine_config(&machine_config_path).expect("Failed to parse machine config"),
+            count.parse().expect("Invalid number of URLs"),
+        );
+        return;
+    }
+    if let Some(url) = get_arg_value(&args, "--test-url") {
+        commands::test_url(
+            url,
+            &config,
+            &parse_machine_config(&machine_config_path).expect("Failed to parse machine config"),
+        );
+        return;
+    }
+
+    let client = Client::new(&config.client, &config.user_agent, &config.urls);
+    if args.iter().any(|arg| arg == "--discover") {
+        commands::discover(
+            &client,
+            &config,
+            &parse_machine_config(&machine_config_path).expect("Failed to parse machine config"),
+        );
+        return;
+    }
+    if let Some(url) = get_arg_value(&args, "--dump-dom") {
+        commands::dump_dom(
+            url,
+            &client,
+            &config,
+            &parse_machine_config(&machine_config_path).expect("Failed to parse machine config"),
+        );
+        return;
+    }
     let mut roots = config.urls.roots.clone();
+    let logs = Logs::new(&config.logs).expect("Failed to open logs");
+    let backoffs = Backoffs::new(&config.client.backoff);
+    let budget = Budget::new(&config);
+
+    pause::init();
 
     info!("Starting crawl URLs");
-    loop {
+    for cycle in 1.. {
+        let now = Instant::now();
+
         roots.shuffle(&mut thread_rng());
 
-        crawl::run(&client, &config, &roots, &machine_config_path);
+        let report = crawl::run(
+            &client,
+            &config,
+            &roots,
+            &machine_config_path,
+            &logs,
+            &backoffs,
+            &budget,
+        );
+
+        let elapsed = now.elapsed();
+        info!(
+            "Crawl cycle {} finished in {} seconds: {} requests ({} failed) to {} hosts, {} discovered URLs, {} bytes",
+            cycle,
+            report.duration.as_secs_f32(),
+            report.requests,
+            report.failed_requests,
+            report.hosts_requests.len(),
+            report.discovered_urls,
+            report.bytes
+        );
+        let mut hosts_requests: Vec<_> = report.hosts_requests.iter().collect();
+        hosts_requests.sort_by_key(|(_, &requests)| Reverse(requests));
+        for (host, requests) in hosts_requests {
+            debug!("`{}`: {} requests", host, requests);
+        }
+
+        if report.deadline_reached {
+            info!("Hard deadline reached, crawling aborted");
+            break;
+        }
+        if budget.bytes_exceeded() {
+            info!(
+                "Downloaded {} bytes, the maximum total bytes reached, crawling stopped",
+                budget.total_bytes()
+            );
+            break;
+        }
+
+        if let Some(interval_secs) = config.client.interval_secs {
+            if let Some(time) = get_interval_sleep(interval_secs, elapsed) {
+                debug!(
+                    "Sleeps for {} seconds before the next cycle",
+                    time.as_secs()
+                );
+                thread::sleep(budget.cap_time(time));
+            } else {
+                // Overlapping cycles are skipped, the next one starts right away
+                warn!(
+                    "Crawl cycle {} took longer than the interval of {} seconds",
+                    cycle, interval_secs
+                );
+            }
+        } else if config.client.dry_run {
+            // Requests aren't sent, so cycles without an interval would follow each other in a busy loop
+            let time = Duration::from_secs(u64::from(crawl::get_random_sleep(&config)));
+            debug!(
+                "Dry run, sleeps for {} seconds before the next cycle",
+                time.as_secs()
+            );
+            thread::sleep(budget.cap_time(time));
+        }
+    }
+}
+
+// Time left of the interval since the start of the cycle, `None` if the cycle took longer
+fn get_interval_sleep(interval_secs: u64, elapsed: Duration) -> Option<Duration> {
+    Duration::from_secs(interval_secs).checked_sub(elapsed)
+}
+
+fn get_arg_value<'a>(args: &'a [String], name: &str) -> Option<&'a str> {
+    args.iter().position(|arg| arg == name).map(|index| {
+        args.get(index + 1)
+            .expect("Missing argument value")
+            .as_str()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interval_sleep() {
+        assert_eq!(
+            get_interval_sleep(60, Duration::from_secs(20)),
+            Some(Duration::from_secs(40))
+        );
+        assert_eq!(
+            get_interval_sleep(60, Duration::from_millis(59_500)),
+            Some(Duration::from_millis(500))
+        );
+        assert_eq!(
+            get_interval_sleep(60, Duration::from_secs(60)),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(get_interval_sleep(60, Duration::from_secs(61)), None);
     }
 }
@@ -0,0 +1,87 @@
+use lazy_static::lazy_static;
+use log::info;
+use std::{
+    sync::{Condvar, Mutex},
+    time::Instant,
+};
+
+lazy_static! {
+    static ref PAUSED: (Mutex<bool>, Condvar) = (Mutex::new(false), Condvar::new());
+}
+
+#[cfg(unix)]
+pub fn init() {
+    use signal_hook::{consts::SIGUSR1, iterator::Signals};
+    use std::thread;
+
+    let mut signals = Signals::new([SIGUSR1]).expect("Failed to register signal handler");
+
+    thread::spawn(move || {
+        for _ in signals.forever() {
+            toggle();
+        }
+    });
+}
+
+#[cfg(not(unix))]
+pub fn init() {}
+
+fn toggle() {
+    let (lock, cvar) = &*PAUSED;
+    let mut paused = lock.lock().unwrap();
+
+    *paused = !*paused;
+    if *paused {
+        info!("Crawling paused");
+    } else {
+        info!("Crawling resumed");
+        cvar.notify_all();
+    }
+}
+
+// The pause doesn't outlast the deadline
+pub fn wait_if_paused(deadline: Option<Instant>) {
+    let (lock, cvar) = &*PAUSED;
+    let guard = lock.lock().unwrap();
+
+    match deadline {
+        Some(deadline) => {
+            let time = deadline.saturating_duration_since(Instant::now());
+            let _guard = cvar
+                .wait_timeout_while(guard, time, |paused| *paused)
+                .unwrap();
+        }
+        None => {
+            let _guard = cvar.wait_while(guard, |paused| *paused).unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{thread, time::Duration};
+
+    #[test]
+    fn paused_until_resumed_or_deadline() {
+        let pause = Duration::from_millis(200);
+
+        toggle();
+        let now = Instant::now();
+        wait_if_paused(Some(now + pause));
+        assert!(now.elapsed() >= pause);
+
+        let now = Instant::now();
+        let resume = thread::spawn(move || {
+            thread::sleep(pause);
+            toggle();
+        });
+        wait_if_paused(None);
+        assert!(now.elapsed() >= pause);
+        resume.join().unwrap();
+
+        let now = Instant::now();
+        wait_if_paused(None);
+        assert!(now.elapsed() < pause);
+    }
+}
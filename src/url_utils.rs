@@ -0,0 +1,306 @@
+use crate::config_reader::Normalize;
+use log::debug;
+use unicode_normalization::UnicodeNormalization;
+use url::{ParseError, Url};
+
+static WWW_PREFIX: &str = "www.";
+
+pub struct UrlNormalizer<'a> {
+    strip_fragment: bool,
+    collapse_www: bool,
+    unicode_paths: bool,
+    tracking_params: &'a [String],
+}
+
+impl<'a> UrlNormalizer<'a> {
+    #[must_use]
+    pub fn new(config: &'a Normalize) -> Self {
+        UrlNormalizer {
+            strip_fragment: config.strip_fragment,
+            collapse_www: config.collapse_www,
+            unicode_paths: config.unicode_paths,
+            tracking_params: &config.tracking_params,
+        }
+    }
+
+    // Default ports are stripped by `Url` itself
+    pub fn normalize(&self, url: &str) -> Result<String, ParseError> {
+        let mut url = Url::parse(url)?;
+
+        if self.strip_fragment {
+            url.set_fragment(None);
+        }
+        if self.collapse_www {
+            if let Some(host) = url.host_str() {
+                let host = strip_www(host).to_string();
+
+                url.set_host(Some(&host))?;
+            }
+        }
+        if self.unicode_paths {
+            // NFC and NFD forms of the same path are percent-encoded differently
+            let segments: Vec<String> = url.path().split('/').map(nfc_path_segment).collect();
+            let nfc_path = segments.join("/");
+
+            if nfc_path != url.path() {
+                url.set_path(&nfc_path);
+            }
+        }
+        if !self.tracking_params.is_empty() && url.query().is_some() {
+            self.strip_tracking_params(&mut url);
+        }
+
+        Ok(url.into())
+    }
+
+    fn strip_tracking_params(&self, url: &mut Url) {
+        let pairs: Vec<(String, String)> = url
+            .query_pairs()
+            .filter(|(key, _)| {
+                !self
+                    .tracking_params
+                    .iter()
+                    .any(|param| key.starts_with(param.as_str()))
+            })
+            .map(|(key, value)| (key.into_owned(), value.into_owned()))
+            .collect();
+
+        if pairs.is_empty() {
+            url.set_query(None);
+        } else {
+            url.query_pairs_mut().clear().extend_pairs(pairs);
+        }
+        debug!("URL without tracking params: `{}`", url);
+    }
+}
+
+// Only escapes of non-ASCII bytes are decoded, so reserved ones (`%2F`, `%25`) stay as they are.
+// Segments that aren't UTF-8 after decoding are left untouched
+fn nfc_path_segment(segment: &str) -> String {
+    let bytes = segment.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+
+    let mut index = 0;
+    while index < bytes.len() {
+        let byte = match (bytes[index], segment.get(index + 1..index + 3)) {
+            (b'%', Some(hex)) => u8::from_str_radix(hex, 16)
+                .ok()
+                .filter(|byte| !byte.is_ascii()),
+            _ => None,
+        };
+        match byte {
+            Some(byte) => {
+                decoded.push(byte);
+                index += 3;
+            }
+            None => {
+                decoded.push(bytes[index]);
+                index += 1;
+            }
+        }
+    }
+
+    match String::from_utf8(decoded) {
+        // Non-ASCII characters are encoded again by `Url::set_path`
+        Ok(decoded) if !decoded.nfc().eq(decoded.chars()) => decoded.nfc().collect(),
+        _ => segment.to_string(),
+    }
+}
+
+// `www.` isn't stripped if the rest is a public suffix (`www.co.uk`)
+#[must_use]
+pub fn strip_www(host: &str) -> &str {
+    match host.strip_prefix(WWW_PREFIX) {
+        Some(rest) if psl::suffix_str(rest) != Some(rest) => rest,
+        _ => host,
+    }
+}
+
+pub fn get_host(url: &str) -> Option<String> {
+    Url::parse(url).ok()?.host_str().map(ToString::to_string)
+}
+
+// `*` matches any characters, including `/`
+pub fn glob_match(glob: &str, text: &str) -> bool {
+    let mut parts = glob.split('*');
+    let first = parts.next().unwrap_or_default();
+    let mut rest = match text.strip_prefix(first) {
+        Some(rest) => rest,
+        None => return false,
+    };
+
+    let parts: Vec<&str> = parts.collect();
+    let (last, middle) = match parts.split_last() {
+        Some(split) => split,
+        // No `*` in the glob
+        None => return rest.is_empty(),
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+
+    rest.ends_with(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> Normalize {
+        Normalize {
+            strip_fragment: true,
+            collapse_www: false,
+            unicode_paths: false,
+            tracking_params: vec!["utm_".to_string(), "fbclid".to_string()],
+        }
+    }
+
+    #[test]
+    fn normalize_composed() {
+        let config = config();
+        let normalizer = UrlNormalizer::new(&config);
+
+        assert_eq!(
+            normalizer
+                .normalize("https://example.com:443/a?utm_source=x&b=1&fbclid=y#top")
+                .unwrap(),
+            "https://example.com/a?b=1"
+        );
+        assert_eq!(
+            normalizer
+                .normalize("http://example.com:80/?utm_medium=x#top")
+                .unwrap(),
+            "http://example.com/"
+        );
+        assert_eq!(
+            normalizer.normalize("http://example.com:8080/a").unwrap(),
+            "http://example.com:8080/a"
+        );
+        assert!(normalizer.normalize("not a url").is_err());
+    }
+
+    #[test]
+    fn normalize_disabled() {
+        let config = Normalize {
+            strip_fragment: false,
+            tracking_params: vec![],
+            ..config()
+        };
+        let normalizer = UrlNormalizer::new(&config);
+
+        assert_eq!(
+            normalizer
+                .normalize("https://example.com:443/a?utm_source=x#top")
+                .unwrap(),
+            "https://example.com/a?utm_source=x#top"
+        );
+    }
+
+    #[test]
+    fn collapse_www() {
+        let config = Normalize {
+            collapse_www: true,
+            ..config()
+        };
+        let normalizer = UrlNormalizer::new(&config);
+
+        assert_eq!(
+            normalizer.normalize("https://www.example.com/a").unwrap(),
+            normalizer.normalize("https://example.com/a").unwrap()
+        );
+        assert_eq!(
+            normalizer.normalize("https://www.co.uk/").unwrap(),
+            "https://www.co.uk/"
+        );
+    }
+
+    #[test]
+    fn www_stripped() {
+        assert_eq!(strip_www("www.example.com"), "example.com");
+        assert_eq!(strip_www("www.example.co.uk"), "example.co.uk");
+        assert_eq!(strip_www("example.com"), "example.com");
+        assert_eq!(strip_www("www2.example.com"), "www2.example.com");
+        assert_eq!(strip_www("blog.www.example.com"), "blog.www.example.com");
+        assert_eq!(strip_www("www.co.uk"), "www.co.uk");
+        assert_eq!(strip_www("www.com"), "www.com");
+    }
+
+    #[test]
+    fn glob() {
+        for (glob, text) in [
+            ("/product/*", "/product/1"),
+            ("/product/*", "/product/a/b"),
+            ("/product/*", "/product/"),
+            ("*", ""),
+            ("*/reviews", "/product/1/reviews"),
+            ("/a/*/c/*", "/a/b/c/d"),
+            ("/exact", "/exact"),
+        ] {
+            assert!(glob_match(glob, text), "{} {}", glob, text);
+        }
+        for (glob, text) in [
+            ("/product/*", "/products/1"),
+            ("*/reviews", "/product/1/reviews/2"),
+            ("/a/*/c/*", "/a/b/d"),
+            ("/exact", "/exact/"),
+            ("/a*a", "/a"),
+        ] {
+            assert!(!glob_match(glob, text), "{} {}", glob, text);
+        }
+    }
+
+    #[test]
+    fn unicode_paths() {
+        let config = Normalize {
+            unicode_paths: true,
+            ..config()
+        };
+        let normalizer = UrlNormalizer::new(&config);
+
+        // `é` as `e` with a combining acute accent (NFD) and as a single character (NFC)
+        let nfc = normalizer
+            .normalize("https://example.com/caf%C3%A9/a")
+            .unwrap();
+        assert_eq!(nfc, "https://example.com/caf%C3%A9/a");
+        assert_eq!(
+            normalizer
+                .normalize("https://example.com/cafe%CC%81/a")
+                .unwrap(),
+            nfc
+        );
+        assert_eq!(
+            normalizer
+                .normalize("https://example.com/cafe\u{301}/a")
+                .unwrap(),
+            nfc
+        );
+        assert_eq!(
+            normalizer
+                .normalize("https://example.com/a%2Fcafe%CC%81%25/b")
+                .unwrap(),
+            "https://example.com/a%2Fcaf%C3%A9%25/b"
+        );
+        // Reserved escapes and segments that aren't UTF-8 are kept
+        assert_eq!(
+            normalizer
+                .normalize("https://example.com/a%2Fb/%25/c")
+                .unwrap(),
+            "https://example.com/a%2Fb/%25/c"
+        );
+        assert_eq!(
+            normalizer
+                .normalize("https://example.com/%FF%FE/cafe%CC%81")
+                .unwrap(),
+            "https://example.com/%FF%FE/caf%C3%A9"
+        );
+        assert_eq!(
+            normalizer
+                .normalize("https://example.com/e%CC%81%FF")
+                .unwrap(),
+            "https://example.com/e%CC%81%FF"
+        );
+    }
+}